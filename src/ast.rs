@@ -0,0 +1,1329 @@
+// SPDX-FileCopyrightText: 2026 Chen Linxuan <me@black-desk.cn>
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Typed document tree built from the [`parser::Event`] stream.
+//!
+//! The tree mirrors the AST of djot.js: a [`Doc`] holds a list of [`Block`]s,
+//! leaf blocks hold [`Inline`]s, and reference definitions and footnotes are
+//! collected on the document. Every node carries its [`Attributes`] and the
+//! [`Span`] of source it was parsed from.
+//!
+//! Unlike djot.js, no identifiers are generated for headings and the document
+//! is not split into sections.
+
+use crate::parser::{self, Container, Event, EventKind, ListStyle};
+
+// ---------------------------------------------------------------------------
+// Public types
+// ---------------------------------------------------------------------------
+
+/// A byte range `start..end` of the source document.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Attributes attached to a node with `{#id .class key=value}`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Attributes {
+    pub id: Option<String>,
+    pub classes: Vec<String>,
+    /// Other `key=value` pairs, in source order.
+    pub pairs: Vec<(String, String)>,
+}
+
+impl Attributes {
+    pub fn is_empty(&self) -> bool {
+        self.id.is_none() && self.classes.is_empty() && self.pairs.is_empty()
+    }
+
+    /// Merge `other` into `self`: classes accumulate, the id and pairs of
+    /// `other` win.
+    pub fn merge(&mut self, other: Attributes) {
+        if other.id.is_some() {
+            self.id = other.id;
+        }
+        self.classes.extend(other.classes);
+        for (key, value) in other.pairs {
+            match self.pairs.iter_mut().find(|(k, _)| *k == key) {
+                Some(pair) => pair.1 = value,
+                None => self.pairs.push((key, value)),
+            }
+        }
+    }
+}
+
+/// A parsed Djot document.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Doc {
+    pub children: Vec<Block>,
+    pub references: Vec<Reference>,
+    pub footnotes: Vec<Footnote>,
+}
+
+impl Doc {
+    /// Look up a reference definition by label.
+    pub fn reference(&self, label: &str) -> Option<&Reference> {
+        self.references.iter().find(|r| r.label == label)
+    }
+
+    /// Look up a footnote by label.
+    pub fn footnote(&self, label: &str) -> Option<&Footnote> {
+        self.footnotes.iter().find(|f| f.label == label)
+    }
+}
+
+/// A reference definition, `[label]: destination`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Reference {
+    pub label: String,
+    pub destination: String,
+    pub attributes: Attributes,
+    pub span: Span,
+}
+
+/// A footnote definition, `[^label]: ...`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Footnote {
+    pub label: String,
+    pub children: Vec<Block>,
+    pub attributes: Attributes,
+    pub span: Span,
+}
+
+/// A block-level node.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Block {
+    pub kind: BlockKind,
+    pub attributes: Attributes,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BlockKind {
+    Para(Vec<Inline>),
+    Heading {
+        level: usize,
+        children: Vec<Inline>,
+    },
+    ThematicBreak,
+    BlockQuote(Vec<Block>),
+    Div(Vec<Block>),
+    CodeBlock {
+        lang: Option<String>,
+        text: String,
+    },
+    RawBlock {
+        format: String,
+        text: String,
+    },
+    BulletList {
+        /// The bullet character, `-`, `*` or `+`.
+        style: char,
+        tight: bool,
+        items: Vec<ListItem>,
+    },
+    OrderedList {
+        /// The style of the enumerator, like `1.`, `a)` or `(I)`.
+        style: ListStyle,
+        start: u64,
+        tight: bool,
+        items: Vec<ListItem>,
+    },
+    TaskList {
+        tight: bool,
+        items: Vec<TaskListItem>,
+    },
+    DefinitionList {
+        tight: bool,
+        items: Vec<DefinitionListItem>,
+    },
+    Table {
+        caption: Option<Caption>,
+        rows: Vec<Row>,
+    },
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ListItem {
+    pub children: Vec<Block>,
+    pub attributes: Attributes,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TaskListItem {
+    pub checked: bool,
+    pub children: Vec<Block>,
+    pub attributes: Attributes,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DefinitionListItem {
+    pub term: Vec<Inline>,
+    pub definition: Vec<Block>,
+    pub attributes: Attributes,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Caption {
+    pub children: Vec<Inline>,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Row {
+    /// Whether the row is followed by a separator line, making it a header.
+    pub head: bool,
+    pub cells: Vec<Cell>,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Cell {
+    pub head: bool,
+    pub alignment: Alignment,
+    pub children: Vec<Inline>,
+    pub span: Span,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Alignment {
+    #[default]
+    Default,
+    Left,
+    Right,
+    Center,
+}
+
+/// An inline node.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Inline {
+    pub kind: InlineKind,
+    pub attributes: Attributes,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InlineKind {
+    Str(String),
+    SoftBreak,
+    HardBreak,
+    NonBreakingSpace,
+    /// A symbol like `:smile:`, holding the alias without colons.
+    Symb(String),
+    Verbatim(String),
+    RawInline {
+        format: String,
+        text: String,
+    },
+    InlineMath(String),
+    DisplayMath(String),
+    Url(String),
+    Email(String),
+    /// A footnote reference like `[^1]`, holding the label.
+    FootnoteReference(String),
+    SmartPunctuation(SmartPunctuation),
+    Emph(Vec<Inline>),
+    Strong(Vec<Inline>),
+    Link {
+        children: Vec<Inline>,
+        destination: Option<String>,
+        reference: Option<String>,
+    },
+    Image {
+        children: Vec<Inline>,
+        destination: Option<String>,
+        reference: Option<String>,
+    },
+    Span(Vec<Inline>),
+    Mark(Vec<Inline>),
+    Superscript(Vec<Inline>),
+    Subscript(Vec<Inline>),
+    Insert(Vec<Inline>),
+    Delete(Vec<Inline>),
+    SingleQuoted(Vec<Inline>),
+    DoubleQuoted(Vec<Inline>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SmartPunctuation {
+    LeftSingleQuote,
+    RightSingleQuote,
+    LeftDoubleQuote,
+    RightDoubleQuote,
+    EnDash,
+    EmDash,
+    Ellipses,
+}
+
+impl SmartPunctuation {
+    /// The annotation of the matching leaf event.
+    pub fn annot(self) -> &'static str {
+        match self {
+            Self::LeftSingleQuote => "left_single_quote",
+            Self::RightSingleQuote => "right_single_quote",
+            Self::LeftDoubleQuote => "left_double_quote",
+            Self::RightDoubleQuote => "right_double_quote",
+            Self::EnDash => "en_dash",
+            Self::EmDash => "em_dash",
+            Self::Ellipses => "ellipses",
+        }
+    }
+
+    /// The Unicode character it renders as.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::LeftSingleQuote => "\u{2018}",
+            Self::RightSingleQuote => "\u{2019}",
+            Self::LeftDoubleQuote => "\u{201c}",
+            Self::RightDoubleQuote => "\u{201d}",
+            Self::EnDash => "\u{2013}",
+            Self::EmDash => "\u{2014}",
+            Self::Ellipses => "\u{2026}",
+        }
+    }
+}
+
+/// Parse a Djot document into a [`Doc`].
+pub fn parse(input: &str) -> Doc {
+    let source = parser::Source::new(input);
+    from_events(source.as_str(), source.events())
+}
+
+/// Build a [`Doc`] from the events the parser produced for `source`.
+pub fn from_events(source: &str, events: impl IntoIterator<Item = Event>) -> Doc {
+    let mut builder = Builder::new(source);
+    for event in events {
+        builder.event(&event);
+    }
+    builder.finish()
+}
+
+// ---------------------------------------------------------------------------
+// Builder
+// ---------------------------------------------------------------------------
+
+/// A row of a table before separator rows are resolved.
+enum RawRow {
+    Data(Row),
+    Separator(Vec<Alignment>),
+}
+
+/// A list item before the list kind is known.
+struct RawItem {
+    checked: Option<bool>,
+    children: Vec<Block>,
+    attributes: Attributes,
+    span: Span,
+}
+
+/// An open container.
+#[derive(Default)]
+struct Frame {
+    /// `None` for the document.
+    container: Option<Container>,
+    start: usize,
+    attributes: Attributes,
+    /// Block attributes waiting for the next child block.
+    pending_attributes: Attributes,
+    blocks: Vec<Block>,
+    inlines: Vec<Inline>,
+    /// Verbatim text of code blocks, inline code, math, links...
+    text: String,
+    /// Label of footnotes and reference definitions, language of code blocks.
+    label: Option<String>,
+    /// Raw format of raw blocks.
+    format: Option<String>,
+    // Lists
+    items: Vec<RawItem>,
+    list_start: Option<u64>,
+    checked: Option<bool>,
+    pending_blank: bool,
+    loose: bool,
+    // Tables
+    rows: Vec<RawRow>,
+    cells: Vec<Cell>,
+    separators: Vec<Alignment>,
+}
+
+struct Builder<'a> {
+    source: &'a str,
+    stack: Vec<Frame>,
+    references: Vec<Reference>,
+    footnotes: Vec<Footnote>,
+}
+
+/// Remove backslash escapes from attribute values.
+fn unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            if let Some(next) = chars.peek().copied() {
+                if next.is_ascii_punctuation() {
+                    out.push(next);
+                    chars.next();
+                    continue;
+                }
+            }
+        }
+        out.push(c);
+    }
+    out
+}
+
+impl<'a> Builder<'a> {
+    fn new(source: &'a str) -> Self {
+        Self {
+            source,
+            stack: vec![Frame::default()],
+            references: Vec::new(),
+            footnotes: Vec::new(),
+        }
+    }
+
+    fn src(&self, event: &Event) -> &'a str {
+        let end = (event.endpos + 1).min(self.source.len());
+        let start = event.startpos.min(end);
+        self.source.get(start..end).unwrap_or("")
+    }
+
+    fn top(&mut self) -> &mut Frame {
+        self.stack
+            .last_mut()
+            .expect("The document frame is never closed")
+    }
+
+    fn finish(mut self) -> Doc {
+        while self.stack.len() > 1 {
+            let container = self.top().container.clone().expect("Not the document");
+            let end = self.source.len();
+            self.close(&container, end);
+        }
+        let doc = self
+            .stack
+            .pop()
+            .expect("The document frame is never closed");
+        Doc {
+            children: doc.blocks,
+            references: self.references,
+            footnotes: self.footnotes,
+        }
+    }
+
+    /// Push an inline, merging adjacent strings.
+    fn push_inline(&mut self, kind: InlineKind, span: Span) {
+        let top = self.top();
+        if let InlineKind::Str(text) = &kind {
+            if let Some(Inline {
+                kind: InlineKind::Str(last),
+                attributes,
+                span: last_span,
+            }) = top.inlines.last_mut()
+            {
+                if attributes.is_empty() {
+                    last.push_str(text);
+                    last_span.end = span.end;
+                    return;
+                }
+            }
+        }
+        top.inlines.push(Inline {
+            kind,
+            attributes: Attributes::default(),
+            span,
+        });
+    }
+
+    fn push_smart(&mut self, punct: SmartPunctuation, span: Span) {
+        self.push_inline(InlineKind::SmartPunctuation(punct), span);
+    }
+
+    /// Push a block, attaching the block attributes preceding it.
+    fn push_block(&mut self, kind: BlockKind, mut attributes: Attributes, span: Span) {
+        let top = self.top();
+        let mut pending = std::mem::take(&mut top.pending_attributes);
+        pending.merge(attributes);
+        attributes = pending;
+        top.blocks.push(Block {
+            kind,
+            attributes,
+            span,
+        });
+    }
+
+    /// The innermost open list.
+    fn list(&mut self) -> Option<&mut Frame> {
+        self.stack
+            .iter_mut()
+            .rev()
+            .find(|f| matches!(f.container, Some(Container::List(_))))
+    }
+
+    fn event(&mut self, event: &Event) {
+        match &event.kind {
+            EventKind::Open(container) => self.open(container.clone(), event),
+            EventKind::Close(container) => {
+                let end = match container {
+                    // These close at the start of the next line.
+                    Container::List(_)
+                    | Container::BlockQuote
+                    | Container::Footnote
+                    | Container::ReferenceDefinition
+                    | Container::Table => event.startpos,
+                    _ => event.endpos + 1,
+                };
+                self.close(container, end.min(self.source.len()));
+            }
+            leaf => self.leaf(leaf.clone(), event),
+        }
+    }
+
+    fn open(&mut self, container: Container, event: &Event) {
+        if container.is_block() {
+            if let Some(list) = self.list() {
+                list.loose |= list.pending_blank;
+                list.pending_blank = false;
+            }
+        }
+
+        let mut frame = Frame {
+            start: event.startpos,
+            ..Default::default()
+        };
+
+        match container {
+            Container::Heading => {
+                frame.list_start = Some(self.src(event).len() as u64);
+            }
+            Container::ListItem(_) => {
+                let marker = self.src(event);
+                if let Some(list) = self.list() {
+                    if let (None, Some(Container::List(styles))) =
+                        (list.list_start, &list.container)
+                    {
+                        let style = styles.first().copied().unwrap_or(ListStyle::Bullet('-'));
+                        list.list_start = Some(style.number(marker));
+                    }
+                }
+            }
+            _ => {}
+        }
+        frame.container = Some(container);
+
+        self.stack.push(frame);
+    }
+
+    fn close(&mut self, container: &Container, end: usize) {
+        // Close events carry no list styles, so only compare the variant.
+        let matches = |frame: &Frame| {
+            frame
+                .container
+                .as_ref()
+                .is_some_and(|c| std::mem::discriminant(c) == std::mem::discriminant(container))
+        };
+        // Tolerate unbalanced events by closing what is open.
+        if !self.stack.iter().any(matches) {
+            log::warn!("Unmatched close event: -{}", container);
+            return;
+        }
+        let mut frame = self.stack.pop().expect("Checked above");
+        while !matches(&frame) {
+            let inner = frame.container.clone().expect("Not the document");
+            self.stack.push(frame);
+            self.close(&inner, end);
+            frame = self.stack.pop().expect("Checked above");
+        }
+        let container = frame.container.take().expect("Not the document");
+
+        let span = Span {
+            start: frame.start,
+            end: end.max(frame.start),
+        };
+
+        match container {
+            // ---- Blocks ----
+            Container::Para => {
+                self.push_block(BlockKind::Para(frame.inlines), frame.attributes, span)
+            }
+            Container::Heading => self.push_block(
+                BlockKind::Heading {
+                    level: frame.list_start.unwrap_or(1) as usize,
+                    children: frame.inlines,
+                },
+                frame.attributes,
+                span,
+            ),
+            Container::BlockQuote => {
+                self.push_block(BlockKind::BlockQuote(frame.blocks), frame.attributes, span)
+            }
+            Container::Div => self.push_block(BlockKind::Div(frame.blocks), frame.attributes, span),
+            Container::CodeBlock => {
+                let kind = match frame.format {
+                    Some(format) => BlockKind::RawBlock {
+                        format,
+                        text: frame.text,
+                    },
+                    None => BlockKind::CodeBlock {
+                        lang: frame.label,
+                        text: frame.text,
+                    },
+                };
+                self.push_block(kind, frame.attributes, span);
+            }
+            Container::List(styles) => {
+                let style = styles.first().copied().unwrap_or(ListStyle::Bullet('-'));
+                let tight = !frame.loose;
+                let kind = match style {
+                    ListStyle::Bullet(bullet) => BlockKind::BulletList {
+                        style: bullet,
+                        tight,
+                        items: frame
+                            .items
+                            .into_iter()
+                            .map(RawItem::into_list_item)
+                            .collect(),
+                    },
+                    ListStyle::Task(_) => BlockKind::TaskList {
+                        tight,
+                        items: frame
+                            .items
+                            .into_iter()
+                            .map(|item| TaskListItem {
+                                checked: item.checked.unwrap_or(false),
+                                children: item.children,
+                                attributes: item.attributes,
+                                span: item.span,
+                            })
+                            .collect(),
+                    },
+                    ListStyle::Definition => BlockKind::DefinitionList {
+                        tight,
+                        items: frame
+                            .items
+                            .into_iter()
+                            .map(RawItem::into_definition_list_item)
+                            .collect(),
+                    },
+                    ListStyle::Ordered(..) => BlockKind::OrderedList {
+                        style,
+                        start: frame.list_start.unwrap_or(1),
+                        tight,
+                        items: frame
+                            .items
+                            .into_iter()
+                            .map(RawItem::into_list_item)
+                            .collect(),
+                    },
+                };
+                // A blank line after a nested list separates blocks of the
+                // enclosing list.
+                let pending_blank = frame.pending_blank;
+                if let Some(parent) = self.list() {
+                    parent.pending_blank |= pending_blank;
+                }
+                self.push_block(kind, frame.attributes, span);
+            }
+            Container::ListItem(_) => {
+                let top = self.top();
+                top.items.push(RawItem {
+                    checked: frame.checked,
+                    children: frame.blocks,
+                    attributes: frame.attributes,
+                    span,
+                });
+            }
+            Container::Table => {
+                let rows = resolve_rows(frame.rows);
+                self.push_block(
+                    BlockKind::Table {
+                        caption: None,
+                        rows,
+                    },
+                    frame.attributes,
+                    span,
+                );
+            }
+            Container::Row => {
+                let top = self.top();
+                if frame.separators.is_empty() {
+                    top.rows.push(RawRow::Data(Row {
+                        head: false,
+                        cells: frame.cells,
+                        span,
+                    }));
+                } else {
+                    top.rows.push(RawRow::Separator(frame.separators));
+                }
+            }
+            Container::Cell => {
+                let top = self.top();
+                top.cells.push(Cell {
+                    head: false,
+                    alignment: Alignment::Default,
+                    children: frame.inlines,
+                    span,
+                });
+            }
+            Container::Caption => {
+                let caption = Caption {
+                    children: frame.inlines,
+                    span,
+                };
+                if let Some(Block {
+                    kind: BlockKind::Table { caption: slot, .. },
+                    ..
+                }) = self.top().blocks.last_mut()
+                {
+                    *slot = Some(caption);
+                }
+            }
+            Container::Footnote => {
+                let mut attributes = std::mem::take(&mut self.top().pending_attributes);
+                attributes.merge(frame.attributes);
+                self.footnotes.push(Footnote {
+                    label: frame.label.unwrap_or_default(),
+                    children: frame.blocks,
+                    attributes,
+                    span,
+                });
+            }
+            Container::ReferenceDefinition => {
+                let mut attributes = std::mem::take(&mut self.top().pending_attributes);
+                attributes.merge(frame.attributes);
+                self.references.push(Reference {
+                    label: frame.label.unwrap_or_default(),
+                    destination: frame.text,
+                    attributes,
+                    span,
+                });
+            }
+            Container::BlockAttributes => {
+                self.top().pending_attributes.merge(frame.attributes);
+            }
+
+            // ---- Inlines ----
+            Container::Emph => self.push_inline(InlineKind::Emph(frame.inlines), span),
+            Container::Strong => self.push_inline(InlineKind::Strong(frame.inlines), span),
+            Container::Span => self.push_inline(InlineKind::Span(frame.inlines), span),
+            Container::Mark => self.push_inline(InlineKind::Mark(frame.inlines), span),
+            Container::Superscript => {
+                self.push_inline(InlineKind::Superscript(frame.inlines), span)
+            }
+            Container::Subscript => self.push_inline(InlineKind::Subscript(frame.inlines), span),
+            Container::Insert => self.push_inline(InlineKind::Insert(frame.inlines), span),
+            Container::Delete => self.push_inline(InlineKind::Delete(frame.inlines), span),
+            Container::SingleQuoted => {
+                self.push_inline(InlineKind::SingleQuoted(frame.inlines), span)
+            }
+            Container::DoubleQuoted => {
+                self.push_inline(InlineKind::DoubleQuoted(frame.inlines), span)
+            }
+            Container::LinkText | Container::ImageText => {
+                let children = frame.inlines;
+                let kind = if container == Container::LinkText {
+                    InlineKind::Link {
+                        children,
+                        destination: None,
+                        reference: None,
+                    }
+                } else {
+                    InlineKind::Image {
+                        children,
+                        destination: None,
+                        reference: None,
+                    }
+                };
+                // Images start at their `!` marker.
+                let span = if container == Container::ImageText {
+                    Span {
+                        start: span.start.saturating_sub(1),
+                        end: span.end,
+                    }
+                } else {
+                    span
+                };
+                self.push_inline(kind, span);
+            }
+            Container::Destination | Container::Reference => {
+                let text = frame.text;
+                let source = self.source;
+                if let Some(link) = self.top().inlines.last_mut() {
+                    // `[text][]` refers to the reference labelled `text`.
+                    let link_text = source
+                        .get(link.span.start..link.span.end)
+                        .unwrap_or("")
+                        .trim_start_matches('!')
+                        .trim_start_matches('[')
+                        .trim_end_matches(']')
+                        .split_whitespace()
+                        .collect::<Vec<_>>()
+                        .join(" ");
+                    link.span.end = span.end;
+                    if let InlineKind::Link {
+                        destination,
+                        reference,
+                        ..
+                    }
+                    | InlineKind::Image {
+                        destination,
+                        reference,
+                        ..
+                    } = &mut link.kind
+                    {
+                        if container == Container::Destination {
+                            *destination = Some(text);
+                        } else if text.is_empty() {
+                            *reference = Some(link_text);
+                        } else {
+                            *reference = Some(text);
+                        }
+                    }
+                }
+            }
+            Container::Verbatim => {
+                // A single space separating the content from backticks in
+                // the delimiters is not part of the content.
+                let mut text = frame.text.as_str();
+                if text.starts_with(" `") {
+                    text = &text[1..];
+                }
+                if text.ends_with("` ") {
+                    text = &text[..text.len() - 1];
+                }
+                self.push_inline(InlineKind::Verbatim(text.to_string()), span);
+            }
+            Container::InlineMath => self.push_inline(InlineKind::InlineMath(frame.text), span),
+            Container::DisplayMath => self.push_inline(InlineKind::DisplayMath(frame.text), span),
+            Container::Url => self.push_inline(InlineKind::Url(frame.text), span),
+            Container::Email => self.push_inline(InlineKind::Email(frame.text), span),
+            Container::Attributes => {
+                let attributes = frame.attributes;
+                attach_inline_attributes(&mut self.top().inlines, attributes);
+            }
+        }
+    }
+
+    fn leaf(&mut self, kind: EventKind, event: &Event) {
+        let text = self.src(event);
+        let span = Span {
+            start: event.startpos,
+            end: (event.endpos + 1).min(self.source.len()),
+        };
+        let parent = self.top().container.clone();
+
+        match kind {
+            EventKind::Open(_) | EventKind::Close(_) => unreachable!("Not a leaf event"),
+            EventKind::Str => match parent {
+                Some(
+                    Container::CodeBlock
+                    | Container::Verbatim
+                    | Container::InlineMath
+                    | Container::DisplayMath
+                    | Container::Url
+                    | Container::Email
+                    | Container::Reference,
+                ) => self.top().text.push_str(text),
+                Some(Container::Destination) => {
+                    let url: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+                    self.top().text.push_str(&url);
+                }
+                _ => self.push_inline(InlineKind::Str(text.to_string()), span),
+            },
+            EventKind::SoftBreak => match parent {
+                Some(Container::Verbatim | Container::InlineMath | Container::DisplayMath) => {
+                    self.top().text.push('\n')
+                }
+                Some(Container::Destination) => {}
+                Some(Container::Reference) => self.top().text.push(' '),
+                _ => self.push_inline(InlineKind::SoftBreak, span),
+            },
+            EventKind::HardBreak => self.push_inline(InlineKind::HardBreak, span),
+            EventKind::NonBreakingSpace => self.push_inline(InlineKind::NonBreakingSpace, span),
+            EventKind::Symb => {
+                let alias = text.trim_matches(':').to_string();
+                self.push_inline(InlineKind::Symb(alias), span);
+            }
+            EventKind::FootnoteReference => {
+                let label = text.trim_start_matches("[^").trim_end_matches(']');
+                self.push_inline(InlineKind::FootnoteReference(label.to_string()), span);
+            }
+            EventKind::LeftSingleQuote => self.push_smart(SmartPunctuation::LeftSingleQuote, span),
+            EventKind::RightSingleQuote => {
+                self.push_smart(SmartPunctuation::RightSingleQuote, span)
+            }
+            EventKind::LeftDoubleQuote => self.push_smart(SmartPunctuation::LeftDoubleQuote, span),
+            EventKind::RightDoubleQuote => {
+                self.push_smart(SmartPunctuation::RightDoubleQuote, span)
+            }
+            EventKind::EnDash => self.push_smart(SmartPunctuation::EnDash, span),
+            EventKind::EmDash => self.push_smart(SmartPunctuation::EmDash, span),
+            EventKind::Ellipses => self.push_smart(SmartPunctuation::Ellipses, span),
+            EventKind::RawFormat => {
+                let format = text
+                    .trim_start_matches('{')
+                    .trim_start_matches('=')
+                    .trim_end_matches('}')
+                    .to_string();
+                if parent == Some(Container::CodeBlock) {
+                    self.top().format = Some(format);
+                } else if let Some(last) = self.top().inlines.last_mut() {
+                    if let InlineKind::Verbatim(code) = &mut last.kind {
+                        last.kind = InlineKind::RawInline {
+                            format,
+                            text: std::mem::take(code),
+                        };
+                        last.span.end = span.end;
+                    }
+                }
+            }
+            EventKind::CodeLanguage => self.top().label = Some(text.to_string()),
+            EventKind::NoteLabel => self.top().label = Some(text.to_string()),
+            EventKind::ReferenceKey => {
+                let label = text.trim_start_matches('[').trim_end_matches(']');
+                self.top().label = Some(label.to_string());
+            }
+            EventKind::ReferenceValue => self.top().text.push_str(text.trim()),
+            EventKind::ThematicBreak => {
+                self.push_block(BlockKind::ThematicBreak, Attributes::default(), span)
+            }
+            EventKind::Blankline => {
+                if let Some(list) = self.list() {
+                    list.pending_blank = true;
+                }
+            }
+            EventKind::CheckboxChecked => self.top().checked = Some(true),
+            EventKind::CheckboxUnchecked => self.top().checked = Some(false),
+            EventKind::SeparatorDefault => self.top().separators.push(Alignment::Default),
+            EventKind::SeparatorLeft => self.top().separators.push(Alignment::Left),
+            EventKind::SeparatorRight => self.top().separators.push(Alignment::Right),
+            EventKind::SeparatorCenter => self.top().separators.push(Alignment::Center),
+            EventKind::Class => self.top().attributes.classes.push(text.to_string()),
+            EventKind::Id => self.top().attributes.id = Some(text.to_string()),
+            EventKind::Key => {
+                let pairs = &mut self.top().attributes.pairs;
+                pairs.push((text.to_string(), String::new()));
+            }
+            EventKind::Value => {
+                let value = unescape(text);
+                let top = self.top();
+                if let Some(pair) = top.attributes.pairs.last_mut() {
+                    if !pair.1.is_empty() {
+                        pair.1.push(' ');
+                    }
+                    pair.1.push_str(&value);
+                }
+            }
+            EventKind::Escape
+            | EventKind::ImageMarker
+            | EventKind::OpenMarker
+            | EventKind::AttrClassMarker
+            | EventKind::AttrIdMarker
+            | EventKind::AttrEqualMarker
+            | EventKind::AttrQuoteMarker
+            | EventKind::AttrSpace
+            | EventKind::Comment => {}
+        }
+    }
+}
+
+impl RawItem {
+    fn into_list_item(self) -> ListItem {
+        ListItem {
+            children: self.children,
+            attributes: self.attributes,
+            span: self.span,
+        }
+    }
+
+    /// The first paragraph of a definition list item is the term.
+    fn into_definition_list_item(self) -> DefinitionListItem {
+        let mut definition = self.children;
+        let term = match definition.first() {
+            Some(Block {
+                kind: BlockKind::Para(_),
+                ..
+            }) => match definition.remove(0).kind {
+                BlockKind::Para(inlines) => inlines,
+                _ => unreachable!(),
+            },
+            _ => Vec::new(),
+        };
+        DefinitionListItem {
+            term,
+            definition,
+            attributes: self.attributes,
+            span: self.span,
+        }
+    }
+}
+
+/// Apply separator rows: the row before a separator is a header, and the
+/// alignments apply to it and all following rows until the next separator.
+fn resolve_rows(raw: Vec<RawRow>) -> Vec<Row> {
+    let mut rows: Vec<Row> = Vec::new();
+    let mut alignments: Vec<Alignment> = Vec::new();
+    let mut last_was_data = false;
+    for raw_row in raw {
+        match raw_row {
+            RawRow::Separator(separators) => {
+                alignments = separators;
+                if last_was_data {
+                    if let Some(header) = rows.last_mut() {
+                        header.head = true;
+                        for (i, cell) in header.cells.iter_mut().enumerate() {
+                            cell.head = true;
+                            cell.alignment = alignments.get(i).copied().unwrap_or_default();
+                        }
+                    }
+                }
+                last_was_data = false;
+            }
+            RawRow::Data(mut row) => {
+                for (i, cell) in row.cells.iter_mut().enumerate() {
+                    cell.alignment = alignments.get(i).copied().unwrap_or_default();
+                }
+                rows.push(row);
+                last_was_data = true;
+            }
+        }
+    }
+    rows
+}
+
+/// Attach inline attributes to the preceding inline. Attributes following a
+/// word apply to that word only, which is split off into a span; attributes
+/// following whitespace apply to nothing.
+fn attach_inline_attributes(inlines: &mut Vec<Inline>, attributes: Attributes) {
+    let Some(last) = inlines.last_mut() else {
+        return;
+    };
+    let InlineKind::Str(text) = &mut last.kind else {
+        last.attributes.merge(attributes);
+        return;
+    };
+    if text.ends_with(char::is_whitespace) {
+        return;
+    }
+    let split = text
+        .rfind(char::is_whitespace)
+        .map(|i| i + text[i..].chars().next().map_or(1, char::len_utf8))
+        .unwrap_or(0);
+    let word = text.split_off(split);
+    let word_span = Span {
+        start: last.span.start + split,
+        end: last.span.end,
+    };
+    let word = Inline {
+        kind: InlineKind::Span(vec![Inline {
+            kind: InlineKind::Str(word),
+            attributes: Attributes::default(),
+            span: word_span,
+        }]),
+        attributes,
+        span: word_span,
+    };
+    if text.is_empty() {
+        *last = word;
+    } else {
+        last.span.end = word_span.start;
+        inlines.push(word);
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Display, in the style of `djot --to ast`
+// ---------------------------------------------------------------------------
+
+struct Printer<'a, 'b> {
+    f: &'a mut std::fmt::Formatter<'b>,
+    depth: usize,
+}
+
+impl Printer<'_, '_> {
+    fn line(
+        &mut self,
+        name: &str,
+        fields: &[(&str, String)],
+        attributes: &Attributes,
+    ) -> std::fmt::Result {
+        write!(self.f, "{}{}", "  ".repeat(self.depth), name)?;
+        for (key, value) in fields {
+            write!(self.f, " {}={:?}", key, value)?;
+        }
+        if let Some(id) = &attributes.id {
+            write!(self.f, " id={:?}", id)?;
+        }
+        if !attributes.classes.is_empty() {
+            write!(self.f, " class={:?}", attributes.classes.join(" "))?;
+        }
+        for (key, value) in &attributes.pairs {
+            write!(self.f, " {}={:?}", key, value)?;
+        }
+        writeln!(self.f)
+    }
+
+    fn nested(&mut self, f: impl FnOnce(&mut Self) -> std::fmt::Result) -> std::fmt::Result {
+        self.depth += 1;
+        let result = f(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn blocks(&mut self, blocks: &[Block]) -> std::fmt::Result {
+        blocks.iter().try_for_each(|block| self.block(block))
+    }
+
+    fn inlines(&mut self, inlines: &[Inline]) -> std::fmt::Result {
+        inlines.iter().try_for_each(|inline| self.inline(inline))
+    }
+
+    fn block(&mut self, block: &Block) -> std::fmt::Result {
+        let attrs = &block.attributes;
+        let none = Attributes::default();
+        match &block.kind {
+            BlockKind::Para(children) => {
+                self.line("para", &[], attrs)?;
+                self.nested(|p| p.inlines(children))
+            }
+            BlockKind::Heading { level, children } => {
+                self.line("heading", &[("level", level.to_string())], attrs)?;
+                self.nested(|p| p.inlines(children))
+            }
+            BlockKind::ThematicBreak => self.line("thematic_break", &[], attrs),
+            BlockKind::BlockQuote(children) => {
+                self.line("block_quote", &[], attrs)?;
+                self.nested(|p| p.blocks(children))
+            }
+            BlockKind::Div(children) => {
+                self.line("div", &[], attrs)?;
+                self.nested(|p| p.blocks(children))
+            }
+            BlockKind::CodeBlock { lang, text } => {
+                let mut fields = Vec::new();
+                if let Some(lang) = lang {
+                    fields.push(("lang", lang.clone()));
+                }
+                fields.push(("text", text.clone()));
+                self.line("code_block", &fields, attrs)
+            }
+            BlockKind::RawBlock { format, text } => self.line(
+                "raw_block",
+                &[("format", format.clone()), ("text", text.clone())],
+                attrs,
+            ),
+            BlockKind::BulletList {
+                style,
+                tight,
+                items,
+            } => {
+                self.line(
+                    "bullet_list",
+                    &[("style", style.to_string()), ("tight", tight.to_string())],
+                    attrs,
+                )?;
+                self.nested(|p| {
+                    items.iter().try_for_each(|item| {
+                        p.line("list_item", &[], &item.attributes)?;
+                        p.nested(|p| p.blocks(&item.children))
+                    })
+                })
+            }
+            BlockKind::OrderedList {
+                style,
+                start,
+                tight,
+                items,
+            } => {
+                self.line(
+                    "ordered_list",
+                    &[
+                        ("style", style.to_string()),
+                        ("start", start.to_string()),
+                        ("tight", tight.to_string()),
+                    ],
+                    attrs,
+                )?;
+                self.nested(|p| {
+                    items.iter().try_for_each(|item| {
+                        p.line("list_item", &[], &item.attributes)?;
+                        p.nested(|p| p.blocks(&item.children))
+                    })
+                })
+            }
+            BlockKind::TaskList { tight, items } => {
+                self.line("task_list", &[("tight", tight.to_string())], attrs)?;
+                self.nested(|p| {
+                    items.iter().try_for_each(|item| {
+                        let checkbox = if item.checked { "checked" } else { "unchecked" };
+                        p.line(
+                            "task_list_item",
+                            &[("checkbox", checkbox.to_string())],
+                            &item.attributes,
+                        )?;
+                        p.nested(|p| p.blocks(&item.children))
+                    })
+                })
+            }
+            BlockKind::DefinitionList { tight, items } => {
+                self.line("definition_list", &[("tight", tight.to_string())], attrs)?;
+                self.nested(|p| {
+                    items.iter().try_for_each(|item| {
+                        p.line("definition_list_item", &[], &item.attributes)?;
+                        p.nested(|p| {
+                            p.line("term", &[], &none)?;
+                            p.nested(|p| p.inlines(&item.term))?;
+                            p.line("definition", &[], &none)?;
+                            p.nested(|p| p.blocks(&item.definition))
+                        })
+                    })
+                })
+            }
+            BlockKind::Table { caption, rows } => {
+                self.line("table", &[], attrs)?;
+                self.nested(|p| {
+                    if let Some(caption) = caption {
+                        p.line("caption", &[], &none)?;
+                        p.nested(|p| p.inlines(&caption.children))?;
+                    }
+                    rows.iter().try_for_each(|row| {
+                        p.line("row", &[("head", row.head.to_string())], &none)?;
+                        p.nested(|p| {
+                            row.cells.iter().try_for_each(|cell| {
+                                let align = match cell.alignment {
+                                    Alignment::Default => "default",
+                                    Alignment::Left => "left",
+                                    Alignment::Right => "right",
+                                    Alignment::Center => "center",
+                                };
+                                p.line(
+                                    "cell",
+                                    &[
+                                        ("head", cell.head.to_string()),
+                                        ("align", align.to_string()),
+                                    ],
+                                    &none,
+                                )?;
+                                p.nested(|p| p.inlines(&cell.children))
+                            })
+                        })
+                    })
+                })
+            }
+        }
+    }
+
+    fn inline(&mut self, inline: &Inline) -> std::fmt::Result {
+        let attrs = &inline.attributes;
+        let (name, children) = match &inline.kind {
+            InlineKind::Str(text) => return self.line("str", &[("text", text.clone())], attrs),
+            InlineKind::SoftBreak => return self.line("soft_break", &[], attrs),
+            InlineKind::HardBreak => return self.line("hard_break", &[], attrs),
+            InlineKind::NonBreakingSpace => return self.line("non_breaking_space", &[], attrs),
+            InlineKind::Symb(alias) => {
+                return self.line("symb", &[("alias", alias.clone())], attrs)
+            }
+            InlineKind::Verbatim(text) => {
+                return self.line("verbatim", &[("text", text.clone())], attrs)
+            }
+            InlineKind::RawInline { format, text } => {
+                return self.line(
+                    "raw_inline",
+                    &[("format", format.clone()), ("text", text.clone())],
+                    attrs,
+                )
+            }
+            InlineKind::InlineMath(text) => {
+                return self.line("inline_math", &[("text", text.clone())], attrs)
+            }
+            InlineKind::DisplayMath(text) => {
+                return self.line("display_math", &[("text", text.clone())], attrs)
+            }
+            InlineKind::Url(text) => return self.line("url", &[("text", text.clone())], attrs),
+            InlineKind::Email(text) => return self.line("email", &[("text", text.clone())], attrs),
+            InlineKind::FootnoteReference(label) => {
+                return self.line("footnote_reference", &[("text", label.clone())], attrs)
+            }
+            InlineKind::SmartPunctuation(punct) => {
+                return self.line(
+                    "smart_punctuation",
+                    &[
+                        ("type", punct.annot().to_string()),
+                        ("text", punct.as_str().to_string()),
+                    ],
+                    attrs,
+                )
+            }
+            InlineKind::Link {
+                children,
+                destination,
+                reference,
+            }
+            | InlineKind::Image {
+                children,
+                destination,
+                reference,
+            } => {
+                let name = match inline.kind {
+                    InlineKind::Link { .. } => "link",
+                    _ => "image",
+                };
+                let mut fields = Vec::new();
+                if let Some(destination) = destination {
+                    fields.push(("destination", destination.clone()));
+                }
+                if let Some(reference) = reference {
+                    fields.push(("reference", reference.clone()));
+                }
+                self.line(name, &fields, attrs)?;
+                return self.nested(|p| p.inlines(children));
+            }
+            InlineKind::Emph(c) => ("emph", c),
+            InlineKind::Strong(c) => ("strong", c),
+            InlineKind::Span(c) => ("span", c),
+            InlineKind::Mark(c) => ("mark", c),
+            InlineKind::Superscript(c) => ("superscript", c),
+            InlineKind::Subscript(c) => ("subscript", c),
+            InlineKind::Insert(c) => ("insert", c),
+            InlineKind::Delete(c) => ("delete", c),
+            InlineKind::SingleQuoted(c) => ("single_quoted", c),
+            InlineKind::DoubleQuoted(c) => ("double_quoted", c),
+        };
+        self.line(name, &[], attrs)?;
+        self.nested(|p| p.inlines(children))
+    }
+}
+
+impl std::fmt::Display for Doc {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut printer = Printer { f, depth: 0 };
+        printer.line("doc", &[], &Attributes::default())?;
+        printer.nested(|p| {
+            p.blocks(&self.children)?;
+            for reference in &self.references {
+                p.line(
+                    "reference",
+                    &[
+                        ("label", reference.label.clone()),
+                        ("destination", reference.destination.clone()),
+                    ],
+                    &reference.attributes,
+                )?;
+            }
+            for footnote in &self.footnotes {
+                p.line(
+                    "footnote",
+                    &[("label", footnote.label.clone())],
+                    &footnote.attributes,
+                )?;
+                p.nested(|p| p.blocks(&footnote.children))?;
+            }
+            Ok(())
+        })
+    }
+}
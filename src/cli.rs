@@ -13,16 +13,66 @@ pub struct Cli {
     #[arg(short, long, action = clap::ArgAction::Count, help = "Set verbosity level")]
     pub verbose: u8,
 
-    #[arg(default_value = "/dev/stdin", help = "Input file(s)")]
+    #[arg(default_value = "/dev/stdin", help = "Input file(s) or directories")]
     pub input: Vec<std::path::PathBuf>,
 
     #[clap(short, help = "Inplace edit <file>s")]
     pub inplace: bool,
 
+    #[clap(
+        long,
+        conflicts_with = "inplace",
+        help = "Check if <file>s are formatted; list the ones that are not and exit with 1"
+    )]
+    pub check: bool,
+
+    #[clap(
+        long,
+        conflicts_with = "inplace",
+        help = "Print a unified diff of the changes formatting would make to <file>s"
+    )]
+    pub diff: bool,
+
+    #[clap(
+        long,
+        help = "Check that formatting keeps the meaning of <file>s and is idempotent, \
+                leave <file>s which fail untouched"
+    )]
+    pub verify: bool,
+
+    #[clap(
+        short,
+        help = "Set the maximum number of allowed columns [default: 72]"
+    )]
+    pub columns: Option<usize>,
+
+    #[clap(
+        long,
+        value_name = "MODE",
+        help = "How to break lines of paragraphs: preserve, reflow or sentence [default: preserve]"
+    )]
+    pub wrap: Option<djotfmt::fmt::Wrap>,
+
+    #[clap(
+        long,
+        value_name = "GLOB",
+        help = "Format files whose name matches <GLOB> in input directories [default: *.dj, *.djot]"
+    )]
+    pub include: Vec<String>,
+
+    #[clap(
+        long,
+        value_name = "GLOB",
+        help = "Skip files and directories matching <GLOB> in input directories"
+    )]
+    pub exclude: Vec<String>,
+
     #[clap(
         short,
-        help = "Set the maximum number of allowed columns",
-        default_value_t = 72
+        long,
+        value_name = "N",
+        default_value_t = 1,
+        help = "Format <file>s on <N> threads, 0 uses one thread per CPU"
     )]
-    pub columns: usize,
+    pub jobs: usize,
 }
@@ -0,0 +1,254 @@
+// SPDX-FileCopyrightText: 2026 Chen Linxuan <me@black-desk.cn>
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Discovery and merging of `djotfmt.toml` configuration files.
+//!
+//! For every input file, djotfmt walks up from the directory containing the
+//! file and collects `djotfmt.toml` (or `.djotfmt.toml`) files. Files closer
+//! to the input override the ones further up, so a sub-directory only needs
+//! to set the options it wants to change. A file containing `root = true`
+//! stops the search.
+//!
+//! Every key mirrors a field of [`FmtConfig`]:
+//!
+//! ```toml
+//! max_cols = 80
+//! wrap = "sentence"
+//! code_fence = "tilde"
+//! list_numbering = "repeat"
+//! list_delimiter = "period"
+//! bullet = "rotate"
+//! list_spacing = "auto"
+//! emphasis = "bare"
+//! div_fence = "shortest"
+//! thematic_break = "---"
+//! thematic_break_fill = false
+//! smart_punctuation = "unicode"
+//! ```
+
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+
+use crate::fmt::{
+    Bullet, CodeFence, DivFence, Emphasis, FmtConfig, ListDelimiter, ListNumbering, ListSpacing,
+    SmartPunctuation, ThematicBreak, Wrap,
+};
+
+/// Configuration file names, in order of preference.
+pub const CONFIG_FILE_NAMES: [&str; 2] = ["djotfmt.toml", ".djotfmt.toml"];
+
+/// The content of a configuration file.
+///
+/// All options are optional: an unset option falls through to the
+/// configuration files further up the tree, then to the default.
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Stop looking for configuration files in parent directories.
+    #[serde(default)]
+    pub root: bool,
+
+    pub max_cols: Option<usize>,
+
+    pub wrap: Option<Wrap>,
+
+    pub code_fence: Option<CodeFence>,
+
+    pub list_numbering: Option<ListNumbering>,
+
+    pub list_delimiter: Option<ListDelimiter>,
+
+    pub bullet: Option<Bullet>,
+
+    pub list_spacing: Option<ListSpacing>,
+
+    pub emphasis: Option<Emphasis>,
+
+    pub div_fence: Option<DivFence>,
+
+    pub thematic_break: Option<ThematicBreak>,
+
+    pub thematic_break_fill: Option<bool>,
+
+    pub smart_punctuation: Option<SmartPunctuation>,
+}
+
+impl Config {
+    /// Parse a configuration file.
+    pub fn from_file(path: &Path) -> std::io::Result<Self> {
+        let content = std::fs::read_to_string(path)?;
+        content.parse().map_err(|e| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), e),
+            )
+        })
+    }
+
+    /// Merge `other` into `self`. Options set in `other` win.
+    pub fn merge(&mut self, other: &Config) {
+        self.root |= other.root;
+        if other.max_cols.is_some() {
+            self.max_cols = other.max_cols;
+        }
+        if other.wrap.is_some() {
+            self.wrap = other.wrap;
+        }
+        if other.code_fence.is_some() {
+            self.code_fence = other.code_fence;
+        }
+        if other.list_numbering.is_some() {
+            self.list_numbering = other.list_numbering;
+        }
+        if other.list_delimiter.is_some() {
+            self.list_delimiter = other.list_delimiter;
+        }
+        if other.bullet.is_some() {
+            self.bullet = other.bullet;
+        }
+        if other.list_spacing.is_some() {
+            self.list_spacing = other.list_spacing;
+        }
+        if other.emphasis.is_some() {
+            self.emphasis = other.emphasis;
+        }
+        if other.div_fence.is_some() {
+            self.div_fence = other.div_fence;
+        }
+        if other.thematic_break.is_some() {
+            self.thematic_break = other.thematic_break;
+        }
+        if other.thematic_break_fill.is_some() {
+            self.thematic_break_fill = other.thematic_break_fill;
+        }
+        if other.smart_punctuation.is_some() {
+            self.smart_punctuation = other.smart_punctuation;
+        }
+    }
+
+    /// Override the options of `config` which are set in this configuration.
+    pub fn apply(&self, config: &mut FmtConfig) {
+        if let Some(max_cols) = self.max_cols {
+            config.max_cols = max_cols;
+        }
+        if let Some(wrap) = self.wrap {
+            config.wrap = wrap;
+        }
+        if let Some(code_fence) = self.code_fence {
+            config.code_fence = code_fence;
+        }
+        if let Some(list_numbering) = self.list_numbering {
+            config.list_numbering = list_numbering;
+        }
+        if let Some(list_delimiter) = self.list_delimiter {
+            config.list_delimiter = list_delimiter;
+        }
+        if let Some(bullet) = self.bullet {
+            config.bullet = bullet;
+        }
+        if let Some(list_spacing) = self.list_spacing {
+            config.list_spacing = list_spacing;
+        }
+        if let Some(emphasis) = self.emphasis {
+            config.emphasis = emphasis;
+        }
+        if let Some(div_fence) = self.div_fence {
+            config.div_fence = div_fence;
+        }
+        if let Some(thematic_break) = self.thematic_break {
+            config.thematic_break = thematic_break;
+        }
+        if let Some(thematic_break_fill) = self.thematic_break_fill {
+            config.thematic_break_fill = thematic_break_fill;
+        }
+        if let Some(smart_punctuation) = self.smart_punctuation {
+            config.smart_punctuation = smart_punctuation;
+        }
+    }
+}
+
+impl std::str::FromStr for Config {
+    type Err = toml::de::Error;
+
+    /// Parse the content of a configuration file.
+    fn from_str(content: &str) -> Result<Self, Self::Err> {
+        toml::from_str(content)
+    }
+}
+
+/// Find the configuration file in `dir`, if any.
+fn find_config_file(dir: &Path) -> Option<PathBuf> {
+    CONFIG_FILE_NAMES
+        .iter()
+        .map(|name| dir.join(name))
+        .find(|path| path.is_file())
+}
+
+/// `path` without `.` and `..` components, so that its ancestors are the
+/// directories it is in. Symbolic links are not resolved.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// Resolves the effective [`Config`] of input files, caching the result of
+/// each visited directory so that sibling files share the work.
+#[derive(Default)]
+pub struct ConfigResolver {
+    cache: HashMap<PathBuf, Config>,
+}
+
+impl ConfigResolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Resolve the merged configuration of the file at `path`.
+    ///
+    /// Standard input is resolved relative to the current directory.
+    pub fn resolve(&mut self, path: &Path) -> std::io::Result<Config> {
+        let dir = match path.parent() {
+            Some(parent) if path != Path::new("/dev/stdin") && !parent.as_os_str().is_empty() => {
+                normalize(&std::path::absolute(parent)?)
+            }
+            _ => std::env::current_dir()?,
+        };
+        self.resolve_dir(&dir)
+    }
+
+    fn resolve_dir(&mut self, dir: &Path) -> std::io::Result<Config> {
+        if let Some(config) = self.cache.get(dir) {
+            return Ok(config.clone());
+        }
+
+        let own = match find_config_file(dir) {
+            Some(file) => {
+                log::debug!("Found configuration file {}", file.display());
+                Some(Config::from_file(&file)?)
+            }
+            None => None,
+        };
+
+        let mut config = match (&own, dir.parent()) {
+            (Some(own), _) if own.root => Config::default(),
+            (_, Some(parent)) => self.resolve_dir(parent)?,
+            (_, None) => Config::default(),
+        };
+        if let Some(own) = &own {
+            config.merge(own);
+        }
+
+        self.cache.insert(dir.to_path_buf(), config.clone());
+        Ok(config)
+    }
+}
@@ -7,25 +7,442 @@
 //! This is an alternative renderer to the one in [`renderer`] — it uses the
 //! new djot.js-based parser instead of `jotdown`.
 
+use std::collections::VecDeque;
+
 use unicode_width::UnicodeWidthStr;
 
-use crate::parser::{self, Event};
+use crate::parser::{self, Container, Delimiter, Enumerator, Event, EventKind, ListStyle};
 
 // ---------------------------------------------------------------------------
 // Public API
 // ---------------------------------------------------------------------------
 
 /// Configuration for the formatter.
+///
+/// Every field can also be set from a configuration file, see
+/// [`crate::config`].
+#[derive(Clone, Debug)]
 pub struct FmtConfig {
     pub max_cols: usize,
+    pub wrap: Wrap,
+    /// The preferred fence of code blocks.
+    pub code_fence: CodeFence,
+    /// How items of ordered lists are numbered.
+    pub list_numbering: ListNumbering,
+    /// The delimiter of ordered list markers.
+    pub list_delimiter: ListDelimiter,
+    /// The bullet of bullet and task lists.
+    pub bullet: Bullet,
+    /// Whether items of lists are separated by blank lines.
+    pub list_spacing: ListSpacing,
+    /// How strong, emphasis, superscript and subscript are delimited.
+    pub emphasis: Emphasis,
+    /// The length of div fences.
+    pub div_fence: DivFence,
+    /// The text of thematic breaks.
+    pub thematic_break: ThematicBreak,
+    /// Whether thematic breaks are repeated up to `max_cols`.
+    pub thematic_break_fill: bool,
+    /// How smart quotes, dashes and ellipses are written.
+    pub smart_punctuation: SmartPunctuation,
+}
+
+impl Default for FmtConfig {
+    fn default() -> Self {
+        Self {
+            max_cols: 72,
+            wrap: Wrap::default(),
+            code_fence: CodeFence::default(),
+            list_numbering: ListNumbering::default(),
+            list_delimiter: ListDelimiter::default(),
+            bullet: Bullet::default(),
+            list_spacing: ListSpacing::default(),
+            emphasis: Emphasis::default(),
+            div_fence: DivFence::default(),
+            thematic_break: ThematicBreak::default(),
+            thematic_break_fill: true,
+            smart_punctuation: SmartPunctuation::default(),
+        }
+    }
+}
+
+/// How lines of paragraphs are broken.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Wrap {
+    /// Keep the line breaks of the source, only wrapping lines longer than
+    /// `max_cols`.
+    #[default]
+    Preserve,
+    /// Treat soft breaks as spaces and refill paragraphs to `max_cols`.
+    Reflow,
+    /// Start every sentence on a new line ("semantic line breaks"), wrapping
+    /// sentences longer than `max_cols`.
+    Sentence,
+}
+
+impl std::str::FromStr for Wrap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "preserve" => Ok(Wrap::Preserve),
+            "reflow" => Ok(Wrap::Reflow),
+            "sentence" => Ok(Wrap::Sentence),
+            _ => Err(format!(
+                "invalid wrap mode {:?}, expected preserve, reflow or sentence",
+                s
+            )),
+        }
+    }
+}
+
+/// The character code block fences are made of.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CodeFence {
+    #[default]
+    Backtick,
+    Tilde,
+}
+
+impl CodeFence {
+    fn char(self) -> char {
+        match self {
+            CodeFence::Backtick => '`',
+            CodeFence::Tilde => '~',
+        }
+    }
+}
+
+/// How items of ordered lists are numbered.
+///
+/// The first item always keeps its number, which is the start of the list.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ListNumbering {
+    /// Number items sequentially from the start of the list.
+    #[default]
+    Sequential,
+    /// Give every item the number of the first one, like `1. 1. 1.`.
+    Repeat,
+    /// Keep the numbers of the source.
+    Preserve,
+}
+
+/// The letter of the `n`th item of an alphabetic list.
+///
+/// Djot list markers have a single letter, so items after the 26th keep
+/// `z`. Only the letter of the first item affects what the list means.
+fn alpha_counter(n: u64) -> char {
+    (b'a' + (n.clamp(1, 26) - 1) as u8) as char
+}
+
+/// The upper case roman numeral of `n`, writing thousands as repeated `M`
+/// past the 3999 of standard numerals.
+fn roman_counter(n: u64) -> String {
+    let n = n.max(1);
+    let mut numeral = "M".repeat((n / 1000) as usize);
+    if !n.is_multiple_of(1000) {
+        numeral.push_str(&roman::to((n % 1000) as i32).unwrap_or_default());
+    }
+    numeral
+}
+
+/// The delimiter written after the numbers of ordered lists.
+///
+/// Djot considers lists with different delimiters different lists, so a
+/// list keeps its delimiter where the one configured would merge it with
+/// the list right before it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ListDelimiter {
+    /// Keep the delimiter of the source.
+    #[default]
+    Preserve,
+    /// `1.`
+    Period,
+    /// `1)`
+    Paren,
+    /// `(1)`
+    Parens,
+}
+
+impl ListDelimiter {
+    fn delimiter(self) -> Option<Delimiter> {
+        match self {
+            ListDelimiter::Preserve => None,
+            ListDelimiter::Period => Some(Delimiter::Period),
+            ListDelimiter::Paren => Some(Delimiter::Paren),
+            ListDelimiter::Parens => Some(Delimiter::Parens),
+        }
+    }
+}
+
+/// The bullet of bullet and task lists.
+///
+/// Djot considers lists with different bullets different lists, so a list
+/// keeps another bullet where the one configured would merge it with the
+/// list right before it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+pub enum Bullet {
+    /// Keep the bullet of the source.
+    #[default]
+    #[serde(rename = "preserve")]
+    Preserve,
+    #[serde(rename = "-")]
+    Dash,
+    #[serde(rename = "*")]
+    Star,
+    #[serde(rename = "+")]
+    Plus,
+    /// Use `-`, `*` and `+` in turn for bullet lists nested in each other.
+    #[serde(rename = "rotate")]
+    Rotate,
+}
+
+/// Whether items of lists are separated by blank lines, making the list
+/// loose, or not, making it tight.
+///
+/// Items with several blocks usually need blank lines between them, so lists
+/// with such items are loose, unless they are tight in the source and kept
+/// as they are.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ListSpacing {
+    /// Keep tight lists tight and loose lists loose.
+    Preserve,
+    /// Make lists tight where possible.
+    Tight,
+    /// Make all lists loose.
+    #[default]
+    Loose,
+    /// Make lists tight if every item is a single paragraph, loose otherwise.
+    Auto,
+}
+
+/// How strong, emphasis, superscript and subscript are delimited.
+///
+/// Insert, delete and mark always need braces.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Emphasis {
+    /// Always with braces, like `{_emphasis_}`.
+    #[default]
+    Braces,
+    /// Without braces, like `_emphasis_`, where this parses the same.
+    Bare,
+}
+
+/// How smart quotes, dashes and ellipses are written.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmartPunctuation {
+    /// Keep the ASCII of the source, like `"`, `{'` or `--`.
+    #[default]
+    Preserve,
+    /// Write quotes without braces where this parses the same.
+    Minimal,
+    /// Write the characters they stand for, like `“` or `–`.
+    Unicode,
+}
+
+/// The length of div fences.
+///
+/// Divs always get longer fences than the divs nested in them, which would
+/// close them otherwise.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DivFence {
+    /// Keep the length of the source.
+    #[default]
+    Preserve,
+    /// Use the shortest fences: `:::` for divs without divs in them, one
+    /// colon more for every level of divs nested in a div.
+    Shortest,
+}
+
+/// The text of thematic breaks: at least three `*` or `-`, all separated by
+/// a space or none, like `* * *` or `---`.
+///
+/// Breaks of fewer marks, or made of `+`, would be list items.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize)]
+#[serde(try_from = "String")]
+pub struct ThematicBreak {
+    mark: char,
+    spaced: bool,
+    count: usize,
+}
+
+impl Default for ThematicBreak {
+    fn default() -> Self {
+        Self {
+            mark: '*',
+            spaced: true,
+            count: 3,
+        }
+    }
+}
+
+impl ThematicBreak {
+    fn separator(self) -> &'static str {
+        if self.spaced {
+            " "
+        } else {
+            ""
+        }
+    }
+
+    /// The break, with as many more marks as fit in `width` columns if set.
+    fn render(self, width: Option<usize>) -> String {
+        let mark = self.mark.to_string();
+        let mut count = self.count;
+        if let Some(width) = width {
+            // Every mark but the last takes a separator.
+            count = count.max(width.div_ceil(1 + self.separator().len()));
+        }
+        vec![mark; count].join(self.separator())
+    }
+}
+
+impl std::str::FromStr for ThematicBreak {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let spaced = s.contains(' ');
+        let count = s.chars().filter(|c| *c != ' ').count();
+        match s.chars().next() {
+            Some(mark @ ('*' | '-')) if count >= 3 => {
+                let thematic_break = Self {
+                    mark,
+                    spaced,
+                    count,
+                };
+                if thematic_break.render(None) == s {
+                    return Ok(thematic_break);
+                }
+            }
+            _ => {}
+        }
+        Err(format!(
+            "invalid thematic break {:?}, expected at least three * or -, like \"* * *\" or \"---\"",
+            s
+        ))
+    }
+}
+
+impl TryFrom<String> for ThematicBreak {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Words ending with a period which do not end a sentence.
+const ABBREVIATIONS: &[&str] = &[
+    "al.", "cf.", "Dr.", "e.g.", "Fig.", "i.e.", "Jr.", "Mr.", "Mrs.", "Ms.", "No.", "pp.",
+    "Prof.", "Sr.", "St.", "vs.",
+];
+
+/// Whether `word`, as written to the output, ends a sentence.
+///
+/// Closing markup and quotes after the final punctuation are skipped, so
+/// `{_done._}` and `"done."` end sentences too. CJK full stops always end
+/// a sentence; a period does unless the word is a known abbreviation or an
+/// initial like `J.`.
+fn ends_sentence(word: &str) -> bool {
+    let word = word.trim_end_matches([
+        '}', '_', '*', '^', '~', '=', '+', '-', '"', '\'', ')', ']', '\u{201d}', '\u{2019}',
+    ]);
+    let Some(last) = word.chars().last() else {
+        return false;
+    };
+    match last {
+        '\u{3002}' | '\u{ff01}' | '\u{ff1f}' | '\u{ff0e}' => true,
+        '!' | '?' => true,
+        '.' => {
+            let bare = word.trim_start_matches(|c: char| !c.is_alphanumeric());
+            let initial = bare.len() == 2 && bare.starts_with(|c: char| c.is_uppercase());
+            !initial && !bare.ends_with("..") && !ABBREVIATIONS.contains(&bare)
+        }
+        _ => false,
+    }
+}
+
+/// Whether a line of a heading starting with `word` may start another block,
+/// ending the heading.
+///
+/// A line only starts a block depending on what follows, like a table row
+/// ending with `|`, so this errs on the side of caution.
+fn may_start_block(word: &str) -> bool {
+    parser::is_list_marker(word)
+        || word.chars().all(|c| c == '#')
+        || word.chars().all(|c| c == '-' || c == '*')
+        || [">", "^"].contains(&word)
+        || ["[", "|", "{", ":::", "```", "~~~"]
+            .iter()
+            .any(|start| word.starts_with(start))
+}
+
+/// Whether a line starting with `word` may close a div, which takes a line
+/// of at least three colons.
+fn may_close_div(word: &str) -> bool {
+    word.len() >= 3 && word.chars().all(|c| c == ':')
+}
+
+/// `word` with a backslash escaping the punctuation which may make a line
+/// starting with it start a block, if that does not change what it means.
+///
+/// Brackets, braces, backticks and tildes may be markup, and escaping a dash
+/// of `--` or `---` would make it a hyphen.
+fn escape_block_start(word: &str) -> Option<String> {
+    if parser::is_list_marker(word) && word.ends_with(['.', ')']) && !word.starts_with('(') {
+        let (number, delimiter) = word.split_at(word.len() - 1);
+        return Some(format!("{}\\{}", number, delimiter));
+    }
+    let first = word.chars().next()?;
+    let escape = match first {
+        '-' | '*' => word.len() == 1,
+        '#' | '>' | '^' | '+' | ':' | '|' | '(' => true,
+        _ => false,
+    };
+    escape.then(|| format!("\\{}", word))
+}
+
+/// Whether the backslash before `escaped` is redundant, escaping punctuation
+/// which means nothing there.
+///
+/// Besides the characters the inline parser looks for, `!` makes links
+/// before it images and `|` separates table cells. Characters which may
+/// start a block, like `#`, are escaped again where they start a paragraph.
+fn redundant_escape(escaped: &str, in_table: bool) -> bool {
+    let mut chars = escaped.chars();
+    match chars.next() {
+        Some('!') => chars.next() != Some('['),
+        Some('|') => !in_table,
+        Some(c) => c.is_ascii_punctuation() && !parser::is_special(c),
+        None => false,
+    }
+}
+
+/// Whether `text` has a `{` which is not closed yet since its last blank
+/// line. Escapes after it may keep it from starting attributes or a comment,
+/// like the one of `{\#id}`.
+fn in_braces(text: &str) -> bool {
+    let paragraph = text.rfind("\n\n").map_or(text, |start| &text[start..]);
+    paragraph
+        .rfind('{')
+        .is_some_and(|open| !paragraph[open..].contains('}'))
 }
 
 /// Format a Djot document and return the formatted string.
 pub fn format(input: &str, config: &FmtConfig) -> String {
-    let events = parser::parse_events(input);
+    let source = parser::Source::new(input);
     let mut writer = FmtWriter::new(input, config);
+    writer.lookahead = Lookahead::scan(source.as_str(), source.events(), config);
     let mut out = String::new();
-    writer.run(&events, &mut out).unwrap();
+    writer.run(source.events(), &mut out).unwrap();
     out
 }
 
@@ -41,19 +458,30 @@ enum Alignment {
     Center,
 }
 
-#[derive(Clone, Debug, PartialEq)]
-enum ListStyle {
-    Dash,
-    Star,
-    Plus,
-    Decimal,
-    AlphaLower,
-    AlphaUpper,
-    RomanLower,
-    RomanUpper,
-    ParenParen,
-    Task,
-    Description,
+#[derive(Default)]
+struct CodeBlockData {
+    /// The language or `=format` of raw blocks, empty if none.
+    info: String,
+    content: String,
+}
+
+impl CodeBlockData {
+    /// The shortest fence made of `fence` which no content line closes,
+    /// that is longer than any run of `fence` starting a content line.
+    fn fence(&self, fence: char) -> String {
+        let longest = self
+            .content
+            .lines()
+            .map(|line| {
+                line.trim_start()
+                    .chars()
+                    .take_while(|c| *c == fence)
+                    .count()
+            })
+            .max()
+            .unwrap_or(0);
+        fence.to_string().repeat((longest + 1).max(3))
+    }
 }
 
 struct TableCellData {
@@ -65,6 +493,322 @@ enum TableRow {
     Separator(Vec<Alignment>),
 }
 
+/// What the spacing of a list depends on, found before it is formatted.
+#[derive(Clone, Copy, Debug, Default)]
+struct ListLayout {
+    /// Blank lines separate items or blocks of items.
+    loose: bool,
+    /// Every item has at most one block, without blank lines in it.
+    single_blocks: bool,
+    /// Every item is a single paragraph.
+    single_paragraphs: bool,
+}
+
+/// What formatting blocks and inlines depends on which only comes after
+/// they start, found in a pass over the events before formatting them.
+#[derive(Default)]
+struct Lookahead {
+    /// The layout of every list, in the order the lists start.
+    lists: VecDeque<ListLayout>,
+    /// Whether each inline container with a [`bare_delimiter`] may be
+    /// written without braces, in the order the containers start.
+    bare_inlines: VecDeque<bool>,
+    /// The fence length of every div, in the order the divs start.
+    div_fences: VecDeque<usize>,
+}
+
+impl Lookahead {
+    fn scan(source: &str, events: impl Iterator<Item = Event>, config: &FmtConfig) -> Self {
+        let mut lists = ListScan::default();
+        let mut inlines = BareInlineScan::default();
+        let mut divs = DivFenceScan::default();
+        for event in events {
+            let kind = &event.kind;
+            if config.list_spacing != ListSpacing::Loose {
+                lists.event(kind);
+            }
+            if config.emphasis == Emphasis::Bare
+                || config.smart_punctuation == SmartPunctuation::Minimal
+            {
+                inlines.event(source, &event, kind);
+            }
+            divs.event(source, &event, kind, config.div_fence);
+        }
+        Self {
+            lists: lists.layouts.into(),
+            bare_inlines: inlines.bare.into(),
+            div_fences: divs.fences.into(),
+        }
+    }
+}
+
+/// Finds the [`ListLayout`] of every list.
+///
+/// A list is loose if a block starts after a blank line in it, a blank line
+/// ending a nested list counting for the enclosing list, as in
+/// [`crate::verify`].
+#[derive(Default)]
+struct ListScan {
+    layouts: Vec<ListLayout>,
+    lists: Vec<OpenList>,
+    // For every open block, whether it is a list item
+    items: Vec<bool>,
+}
+
+struct OpenList {
+    index: usize,
+    pending_blank: bool,
+    /// Number of blocks of the current item.
+    blocks: usize,
+}
+
+impl ListScan {
+    fn event(&mut self, kind: &EventKind) {
+        match kind {
+            EventKind::Open(container) if container.is_block() => {
+                let is_item = matches!(container, Container::ListItem(_));
+                if let Some(list) = self.lists.last_mut() {
+                    let layout = &mut self.layouts[list.index];
+                    if list.pending_blank {
+                        layout.loose = true;
+                        layout.single_blocks &= is_item;
+                        list.pending_blank = false;
+                    }
+                    if is_item {
+                        list.blocks = 0;
+                    } else if self.items.last() == Some(&true) {
+                        list.blocks += 1;
+                        layout.single_blocks &= list.blocks == 1;
+                        layout.single_paragraphs &=
+                            list.blocks == 1 && *container == Container::Para;
+                    }
+                }
+                if let Container::List(_) = container {
+                    self.lists.push(OpenList {
+                        index: self.layouts.len(),
+                        pending_blank: false,
+                        blocks: 0,
+                    });
+                    self.layouts.push(ListLayout {
+                        loose: false,
+                        single_blocks: true,
+                        single_paragraphs: true,
+                    });
+                }
+                self.items.push(is_item);
+            }
+            EventKind::Close(container) if container.is_block() => {
+                self.items.pop();
+                if let Container::List(_) = container {
+                    if let Some(list) = self.lists.pop() {
+                        if let Some(parent) = self.lists.last_mut() {
+                            parent.pending_blank |= list.pending_blank;
+                        }
+                    }
+                }
+            }
+            EventKind::Blankline => {
+                if let Some(list) = self.lists.last_mut() {
+                    list.pending_blank = true;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The delimiter of inline containers which may be written without braces.
+fn bare_delimiter(container: &Container) -> Option<char> {
+    match container {
+        Container::Strong => Some('*'),
+        Container::Emph => Some('_'),
+        Container::Superscript => Some('^'),
+        Container::Subscript => Some('~'),
+        Container::SingleQuoted => Some('\''),
+        Container::DoubleQuoted => Some('"'),
+        _ => None,
+    }
+}
+
+/// Whether a single quote after `before` may open a quotation.
+fn single_quote_opens(before: Option<char>) -> bool {
+    before.is_none_or(|c| c.is_whitespace() || matches!(c, '"' | '\'' | '-' | '(' | '['))
+}
+
+/// Finds whether each inline container with a [`bare_delimiter`], and each
+/// right single quote, may be written without braces.
+///
+/// A right single quote, usually an apostrophe, may be written as a bare
+/// `'` which can neither open a quotation nor close one left open.
+///
+/// Without braces, a delimiter only opens before a non-space character and
+/// only closes after one, matching the last opener of the same kind. So the
+/// content must not start or end with whitespace nor contain the delimiter,
+/// and the opener must not close a delimiter left unmatched earlier, which
+/// it cannot after whitespace. A `{` before or a `}` after would make the
+/// delimiters explicit ones.
+///
+/// An unmatched quote with braces inside a quotation would match the
+/// explicit delimiters of the quotation instead, so such a quotation is kept
+/// without braces.
+#[derive(Default)]
+struct BareInlineScan {
+    bare: Vec<bool>,
+    // Index in `bare`, delimiter and whether an unmatched quote with braces is
+    // inside, of the open containers
+    open: Vec<(usize, char, bool)>,
+    // Delimiters written as text in the current block
+    seen: Vec<char>,
+    // Depth of containers whose text is not parsed for inlines
+    verbatim: usize,
+}
+
+impl BareInlineScan {
+    fn event(&mut self, source: &str, event: &Event, kind: &EventKind) {
+        let before = source
+            .get(..event.startpos)
+            .and_then(|s| s.chars().next_back());
+        let after = source
+            .get(event.endpos + 1..)
+            .and_then(|s| s.chars().next());
+        match kind {
+            EventKind::Open(container) | EventKind::Close(container) if container.is_block() => {
+                self.seen.clear();
+            }
+            EventKind::Open(container) => {
+                if let Some(delimiter) = bare_delimiter(container) {
+                    let opens = after.is_some_and(|c| !c.is_whitespace())
+                        && (delimiter != '\'' || single_quote_opens(before))
+                        && match before {
+                            None => true,
+                            Some(c) if c.is_whitespace() => true,
+                            Some('{' | '\\') => false,
+                            Some(_) => !self.seen.contains(&delimiter),
+                        };
+                    // Nested in itself, the inner opener could close it.
+                    for (index, _, _) in self.open.iter().filter(|(_, d, _)| *d == delimiter) {
+                        self.bare[*index] = false;
+                    }
+                    self.open.push((self.bare.len(), delimiter, false));
+                    self.bare.push(opens);
+                } else if let Container::Verbatim
+                | Container::InlineMath
+                | Container::DisplayMath
+                | Container::Url
+                | Container::Email = container
+                {
+                    self.verbatim += 1;
+                }
+            }
+            EventKind::Close(container) => {
+                if bare_delimiter(container).is_some() {
+                    if let Some((index, _, explicit_inside)) = self.open.pop() {
+                        let closes = before.is_some_and(|c| !c.is_whitespace());
+                        self.bare[index] &= closes && after != Some('}');
+                        self.bare[index] |= explicit_inside;
+                    }
+                } else if let Container::Verbatim
+                | Container::InlineMath
+                | Container::DisplayMath
+                | Container::Url
+                | Container::Email = container
+                {
+                    self.verbatim -= 1;
+                }
+            }
+            EventKind::Str if self.verbatim == 0 => {
+                let text = source
+                    .get(event.startpos..(event.endpos + 1).min(source.len()))
+                    .unwrap_or("");
+                for (index, delimiter, _) in &self.open {
+                    if text.contains(*delimiter) {
+                        self.bare[*index] = false;
+                    }
+                }
+                self.seen
+                    .extend(text.chars().filter(|c| matches!(c, '*' | '_' | '^' | '~')));
+            }
+            EventKind::LeftSingleQuote
+            | EventKind::RightSingleQuote
+            | EventKind::LeftDoubleQuote
+            | EventKind::RightDoubleQuote => {
+                let quote = match kind {
+                    EventKind::LeftDoubleQuote | EventKind::RightDoubleQuote => '"',
+                    _ => '\'',
+                };
+                let opens = quote == '"' || single_quote_opens(before);
+                let bare = *kind == EventKind::RightSingleQuote
+                    && !opens
+                    && !matches!(before, Some('{' | '\\'))
+                    && !self.seen.contains(&quote)
+                    && after != Some('}');
+                if *kind == EventKind::RightSingleQuote {
+                    self.bare.push(bare);
+                }
+                // The source of the quote includes its braces.
+                let text = source
+                    .get(event.startpos..(event.endpos + 1).min(source.len()))
+                    .unwrap_or("");
+                let braced = !bare && (text.starts_with('{') || text.ends_with('}'));
+                for (index, delimiter, explicit_inside) in &mut self.open {
+                    if *delimiter == quote {
+                        if braced {
+                            *explicit_inside = true;
+                        } else {
+                            self.bare[*index] = false;
+                        }
+                    }
+                }
+                if opens && !braced {
+                    self.seen.push(quote);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Finds the fence length of every div.
+///
+/// A fence at least as long as the opening one closes a div, even inside
+/// a div nested in it, so a div needs a longer fence than every div in it.
+#[derive(Default)]
+struct DivFenceScan {
+    fences: Vec<usize>,
+    // Index in `fences` and length of the longest fence inside of the open
+    // divs
+    open: Vec<(usize, usize)>,
+}
+
+impl DivFenceScan {
+    fn event(&mut self, source: &str, event: &Event, kind: &EventKind, style: DivFence) {
+        match kind {
+            EventKind::Open(Container::Div) => {
+                self.open.push((self.fences.len(), 0));
+                // The source of the div is its opening fence.
+                let fence = source[event.startpos..]
+                    .chars()
+                    .take_while(|c| *c == ':')
+                    .count();
+                self.fences.push(match style {
+                    DivFence::Preserve => fence,
+                    DivFence::Shortest => 0,
+                });
+            }
+            EventKind::Close(Container::Div) => {
+                if let Some((index, inner)) = self.open.pop() {
+                    let fence = self.fences[index].max(inner + 1).max(3);
+                    self.fences[index] = fence;
+                    if let Some((_, parent_inner)) = self.open.last_mut() {
+                        *parent_inner = (*parent_inner).max(fence);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
 struct TableData {
     /// All rows in order (data and separator interleaved).
     rows: Vec<TableRow>,
@@ -131,6 +875,7 @@ impl AttrState {
 struct FmtWriter<'a> {
     source: &'a str,
     max_cols: usize,
+    wrap_mode: Wrap,
 
     // Word-buffer (same pattern as existing Writer)
     pending_line: String,
@@ -146,6 +891,21 @@ struct FmtWriter<'a> {
 
     // List tracking
     list_style_stack: Vec<ListStyle>,
+    // Whether each open list is tight
+    list_tight: Vec<bool>,
+    // For every open block, whether it is a list item
+    block_items: Vec<bool>,
+    list_spacing: ListSpacing,
+
+    // What the blocks and inlines not started yet depend on. Lists have no
+    // layouts with `ListSpacing::Loose`, and inlines are only bare with
+    // `Emphasis::Bare` or `SmartPunctuation::Minimal`.
+    lookahead: Lookahead,
+    emphasis: Emphasis,
+    smart_punctuation: SmartPunctuation,
+
+    // Whether each open inline container is written without braces
+    bare_stack: Vec<bool>,
 
     // Table
     table_data: Option<TableData>,
@@ -159,11 +919,24 @@ struct FmtWriter<'a> {
     // Track whether we need to emit the `{` for an inline attribute
     in_inline_attrs: bool,
 
-    // Verbatim opening backtick sequence (for matching close)
-    verbatim_ticks: String,
+    // Inline verbatim content, buffered until its delimiters are known.
+    // Left empty while the content is written out, `None` outside verbatim.
+    verbatim: Option<String>,
+
+    // Code block, buffered until its fence is known
+    code_block: Option<CodeBlockData>,
+    code_fence: CodeFence,
+
+    list_numbering: ListNumbering,
+    list_delimiter: ListDelimiter,
+    bullet: Bullet,
 
-    // For code_block: track if we need to emit language
-    code_block_need_lang: bool,
+    thematic_break: ThematicBreak,
+    thematic_break_fill: bool,
+
+    // Style of the list just closed, if nothing but blank lines followed it:
+    // a list starting there with the same style would continue it
+    previous_list: Option<ListStyle>,
 
     // Heading level tracking (parsed from source)
     heading_level: usize,
@@ -179,9 +952,28 @@ struct FmtWriter<'a> {
     // Whether we just saw +linktext (need to close with ] on next dest/ref)
     pending_link_close: bool,
 
-    /// True between +div and the class event — class name should go to raw output.
+    /// True between +div and the next event, which may be the class written
+    /// on the opening fence line.
     div_needs_class: bool,
 
+    /// Fences of the open divs.
+    div_fences: Vec<String>,
+
+    /// True after a soft break kept or a sentence ended: the next word starts
+    /// a new line, unless that line could start a block.
+    line_break: bool,
+
+    /// True after a hard break, which the next word must start a line after.
+    hard_break: bool,
+
+    /// True at the start of a paragraph, where the first word could start
+    /// another block.
+    para_start: bool,
+    /// The text of the paragraph so far, without prefixes, while its first
+    /// word could start another block which cannot be escaped, like `[a]:` or
+    /// `~~~`. Its lines are only broken where it still reads as a paragraph.
+    para_text: Option<String>,
+
     /// True while inside +destination … -destination (link URL).
     in_destination: bool,
 
@@ -194,6 +986,7 @@ impl<'a> FmtWriter<'a> {
         Self {
             source,
             max_cols: config.max_cols,
+            wrap_mode: config.wrap,
             pending_line: String::new(),
             pending_word: String::new(),
             space_after_pending_word: false,
@@ -203,17 +996,36 @@ impl<'a> FmtWriter<'a> {
             list_item_start: false,
             prefix: Vec::new(),
             list_style_stack: Vec::new(),
+            list_tight: Vec::new(),
+            block_items: Vec::new(),
+            list_spacing: config.list_spacing,
+            lookahead: Lookahead::default(),
+            emphasis: config.emphasis,
+            smart_punctuation: config.smart_punctuation,
+            bare_stack: Vec::new(),
             table_data: None,
             attr: AttrState::new(),
             in_block_attrs: false,
             in_inline_attrs: false,
-            verbatim_ticks: String::new(),
-            code_block_need_lang: false,
+            verbatim: None,
+            code_block: None,
+            code_fence: config.code_fence,
+            list_numbering: config.list_numbering,
+            list_delimiter: config.list_delimiter,
+            bullet: config.bullet,
+            thematic_break: config.thematic_break,
+            thematic_break_fill: config.thematic_break_fill,
+            previous_list: None,
             heading_level: 0,
             in_ref_def: false,
             have_content: false,
             pending_link_close: false,
             div_needs_class: false,
+            div_fences: Vec::new(),
+            line_break: false,
+            hard_break: false,
+            para_start: false,
+            para_text: None,
             in_destination: false,
             ref_def_url: String::new(),
         }
@@ -244,20 +1056,40 @@ impl<'a> FmtWriter<'a> {
         length += self.pending_word.width();
         let length = length;
 
-        if !self.no_wrap
+        let starts_block = self.starts_block(&self.pending_word);
+        let hard_break = std::mem::take(&mut self.hard_break);
+        let para_start = std::mem::take(&mut self.para_start);
+        if (hard_break && starts_block) || (para_start && may_start_block(&self.pending_word)) {
+            match escape_block_start(&self.pending_word) {
+                Some(word) => self.pending_word = word,
+                None if para_start => self.para_text = Some(String::new()),
+                None => {}
+            }
+        }
+
+        let overflow = !self.no_wrap
             && !self.list_item_start
             && length > self.max_cols
             && !self.pending_line.is_empty()
-            && self.table_data.is_none()
-        {
+            && self.table_data.is_none();
+
+        // A word which could start a block stays on the line before, and so
+        // does one after a first line which would start a block.
+        if (self.line_break || overflow) && !starts_block && !self.para_starts_block() {
             self.wrap(out)?;
         } else if self.space_after_pending_word {
             self.pending_line.push(' ');
             log::trace!("Pending line: {:?}", self.pending_line);
+            if let Some(text) = &mut self.para_text {
+                text.push(' ');
+            }
         }
 
         self.apply_prefix();
         self.pending_line.push_str(&self.pending_word);
+        if let Some(text) = &mut self.para_text {
+            text.push_str(&self.pending_word);
+        }
         log::trace!("Pending line: {:?}", self.pending_line);
         self.pending_word.clear();
         self.space_after_pending_word = space_after;
@@ -284,11 +1116,43 @@ impl<'a> FmtWriter<'a> {
         out.write_str(self.pending_line.trim_end())?;
         out.write_str("\n")?;
         self.pending_line.clear();
+        if let Some(text) = &mut self.para_text {
+            text.push('\n');
+        }
         self.space_after_pending_word = false;
+        self.line_break = false;
+        self.hard_break = false;
         self.have_content = true;
         Ok(())
     }
 
+    /// Whether the paragraph written so far starts with another block, so
+    /// that the current line cannot end yet.
+    fn para_starts_block(&mut self) -> bool {
+        let Some(text) = &self.para_text else {
+            return false;
+        };
+        let starts_block = parser::Source::new(text)
+            .events()
+            .next()
+            .is_some_and(|event| event.kind != EventKind::Open(Container::Para));
+        // Only block attributes span lines, the first line decides the rest.
+        if !starts_block && !parser::attributes_may_continue(text) {
+            self.para_text = None;
+        }
+        starts_block
+    }
+
+    /// Whether a line of the current block starting with `word` may start a
+    /// block instead. Lines of paragraphs never do, except ones closing a div.
+    fn starts_block(&self, word: &str) -> bool {
+        if self.heading_level > 0 {
+            may_start_block(word)
+        } else {
+            !self.div_fences.is_empty() && may_close_div(word)
+        }
+    }
+
     fn apply_prefix(&mut self) {
         if !self.pending_line.is_empty() {
             return;
@@ -327,36 +1191,64 @@ impl<'a> FmtWriter<'a> {
     }
 
     // -----------------------------------------------------------------------
-    // List style parsing
+    // List styles
     // -----------------------------------------------------------------------
 
-    fn parse_list_style(annot: &str) -> ListStyle {
-        // annot is like "+list|-" or "+list|1." or "+list|-X"
-        let rest = annot.split_once('|').map(|(_, r)| r).unwrap_or("");
-        if rest.starts_with("-X") || rest.starts_with("*X") || rest.starts_with("+X") {
-            ListStyle::Task
-        } else if rest == ":" || rest == ":|" {
-            ListStyle::Description
-        } else if rest == "-" || rest == "*" || rest == "+" {
-            match rest {
-                "-" => ListStyle::Dash,
-                "*" => ListStyle::Star,
-                "+" => ListStyle::Plus,
-                _ => ListStyle::Dash,
-            }
-        } else if rest.starts_with('(') {
-            ListStyle::ParenParen
-        } else if let Some(ch) = rest.chars().next() {
-            match ch {
-                '1' => ListStyle::Decimal,
-                'a' => ListStyle::AlphaLower,
-                'A' => ListStyle::AlphaUpper,
-                'i' => ListStyle::RomanLower,
-                'I' => ListStyle::RomanUpper,
-                _ => ListStyle::Dash,
-            }
-        } else {
-            ListStyle::Dash
+    /// The style to write a list of style `style` with, which must differ
+    /// from the style of an adjacent list before it.
+    fn output_list_style(&self, style: ListStyle) -> ListStyle {
+        let bullet_style = |bullet: char, task: bool| {
+            let wanted = match self.bullet {
+                Bullet::Preserve => bullet,
+                Bullet::Dash => '-',
+                Bullet::Star => '*',
+                Bullet::Plus => '+',
+                Bullet::Rotate => {
+                    let depth = self
+                        .list_style_stack
+                        .iter()
+                        .filter(|style| matches!(style, ListStyle::Bullet(_) | ListStyle::Task(_)))
+                        .count();
+                    ['-', '*', '+'][depth % 3]
+                }
+            };
+            [wanted, bullet, '-', '*']
+                .into_iter()
+                .map(|bullet| {
+                    if task {
+                        ListStyle::Task(bullet)
+                    } else {
+                        ListStyle::Bullet(bullet)
+                    }
+                })
+                .find(|style| self.previous_list != Some(*style))
+                .unwrap_or(style)
+        };
+        let (enumerator, source) = match style {
+            ListStyle::Bullet(bullet) => return bullet_style(bullet, false),
+            ListStyle::Task(bullet) => return bullet_style(bullet, true),
+            ListStyle::Definition => return style,
+            ListStyle::Ordered(enumerator, source) => (enumerator, source),
+        };
+        let Some(delimiter) = self.list_delimiter.delimiter() else {
+            return style;
+        };
+        [delimiter, source, Delimiter::Period, Delimiter::Paren]
+            .into_iter()
+            .find(|delimiter| match self.previous_list {
+                Some(ListStyle::Ordered(previous, previous_delimiter)) => {
+                    previous_delimiter != *delimiter || !previous.overlaps(enumerator)
+                }
+                _ => true,
+            })
+            .map_or(style, |delimiter| ListStyle::Ordered(enumerator, delimiter))
+    }
+
+    /// The bullet of the items of the innermost task list.
+    fn task_bullet(&self) -> char {
+        match self.list_style_stack.last() {
+            Some(ListStyle::Task(bullet)) => *bullet,
+            _ => '-',
         }
     }
 
@@ -432,6 +1324,33 @@ impl<'a> FmtWriter<'a> {
         Ok(())
     }
 
+    // -----------------------------------------------------------------------
+    // Code block rendering
+    // -----------------------------------------------------------------------
+
+    fn render_code_block<W: std::fmt::Write>(
+        &mut self,
+        code: CodeBlockData,
+        out: &mut W,
+    ) -> std::fmt::Result {
+        let fence = code.fence(self.code_fence.char());
+        self.apply_prefix();
+        self.push_raw(&fence)?;
+        if !code.info.is_empty() {
+            self.push_raw(" ")?;
+            self.push_raw(&code.info)?;
+        }
+        self.wrap(out)?;
+        for line in code.content.lines() {
+            self.apply_prefix();
+            self.push_raw(line)?;
+            self.wrap(out)?;
+        }
+        self.apply_prefix();
+        self.push_raw(&fence)?;
+        self.wrap(out)
+    }
+
     // -----------------------------------------------------------------------
     // Table rendering
     // -----------------------------------------------------------------------
@@ -563,781 +1482,779 @@ impl<'a> FmtWriter<'a> {
     }
     // -----------------------------------------------------------------------
 
-    fn run<W: std::fmt::Write>(&mut self, events: &[Event], out: &mut W) -> std::fmt::Result {
+    fn run<W: std::fmt::Write>(
+        &mut self,
+        events: impl Iterator<Item = Event>,
+        out: &mut W,
+    ) -> std::fmt::Result {
         log::trace!("Start fmt render events");
 
-        // List item counter per list nesting level
-        let mut list_counter: Vec<u64> = Vec::new();
+        // Start and number of items so far of the open lists
+        let mut list_counter: Vec<(u64, u64)> = Vec::new();
 
-        let mut i = 0;
-        while i < events.len() {
-            let event = &events[i];
-            let annot = &event.annot;
-            log::debug!("Event: {} {:?}", annot, self.src(event));
-
-            if annot.starts_with('+') || annot.starts_with('-') {
-                let is_open = annot.starts_with('+');
-                let tag = &annot[1..];
-
-                // Strip the |... suffix for matching
-                let base_tag = tag.split('|').next().unwrap_or(tag);
-
-                match base_tag {
-                    // ---- Block containers ----
-                    "para" => {
-                        if is_open {
-                            self.blankline(out)?;
-                        } else {
-                            if !self.pending_word.is_empty() {
-                                self.commit_word(false, out)?;
-                            }
-                            self.wrap(out)?;
-                            self.need_blankline = true;
-                        }
-                    }
-                    "heading" => {
-                        if is_open {
-                            self.blankline(out)?;
-                            self.apply_prefix();
-                            // Extract heading level from source (e.g. "##" => level 2)
-                            let src = self.src(event);
-                            let level = src.chars().take_while(|c| *c == '#').count();
-                            self.heading_level = level;
-                            self.push_raw(&"#".repeat(level))?;
-                            self.push_raw(" ")?;
-                            self.prefix.push(" ".repeat(level + 1));
+        for event in events {
+            let event = &event;
+            let kind = &event.kind;
+            log::debug!("Event: {} {:?}", kind, self.src(event));
+
+            if self.div_needs_class && *kind != EventKind::Class {
+                self.end_div_fence(out)?;
+            }
+
+            match kind {
+                EventKind::Open(container) | EventKind::Close(container) => {
+                    let is_open = matches!(kind, EventKind::Open(_));
+                    if container.is_block() && !matches!(container, Container::List(_)) {
+                        self.previous_list = None;
+                    }
+                    if container.is_block() {
+                        let is_item = matches!(container, Container::ListItem(_));
+                        if !is_open {
+                            self.block_items.pop();
                         } else {
-                            if !self.pending_word.is_empty() {
-                                self.commit_word(false, out)?;
+                            // Blocks of items of tight lists are not separated
+                            // by blank lines, which would make the lists loose.
+                            if !is_item
+                                && self.block_items.last() == Some(&true)
+                                && self.list_tight.last() == Some(&true)
+                            {
+                                self.need_blankline = false;
                             }
-                            self.wrap(out)?;
-                            self.prefix.pop();
-                            self.need_blankline = true;
-                        }
-                    }
-                    "block_quote" => {
-                        if is_open {
-                            self.blankline(out)?;
-                            self.prefix.push("> ".to_string());
-                        } else {
-                            self.prefix.pop();
-                        }
-                    }
-                    "list" => {
-                        if is_open {
-                            self.blankline(out)?;
-                            let style = Self::parse_list_style(annot);
-                            self.list_style_stack.push(style);
-                            list_counter.push(0);
-                        } else {
-                            self.list_style_stack.pop();
-                            list_counter.pop();
+                            self.block_items.push(is_item);
                         }
                     }
-                    "list_item" => {
-                        if is_open {
-                            self.blankline(out)?;
-                            self.apply_prefix();
-                            let style = self
-                                .list_style_stack
-                                .last()
-                                .cloned()
-                                .unwrap_or(ListStyle::Dash);
-                            *list_counter.last_mut().unwrap() += 1;
-                            let counter = *list_counter.last().unwrap();
-
-                            match style {
-                                ListStyle::Dash => {
-                                    self.push_raw("- ")?;
-                                    self.prefix.push("  ".to_string());
-                                }
-                                ListStyle::Star => {
-                                    self.push_raw("* ")?;
-                                    self.prefix.push("  ".to_string());
-                                }
-                                ListStyle::Plus => {
-                                    self.push_raw("+ ")?;
-                                    self.prefix.push("  ".to_string());
-                                }
-                                ListStyle::Task => {
-                                    // marker and prefix emitted by checkbox_* event
-                                }
-                                ListStyle::Description => {
-                                    self.push_raw(": ")?;
-                                    self.prefix.push("  ".to_string());
-                                }
-                                ListStyle::Decimal => {
-                                    let n = counter.to_string();
-                                    self.push_raw(&n)?;
-                                    self.push_raw(". ")?;
-                                    let w = n.len() + 2;
-                                    self.prefix.push(" ".repeat(w));
-                                }
-                                ListStyle::AlphaLower => {
-                                    let ch = ((counter as u8 - 1) + b'a') as char;
-                                    let n = ch.to_string();
-                                    self.push_raw(&n)?;
-                                    self.push_raw(". ")?;
-                                    self.prefix.push("   ".to_string());
-                                }
-                                ListStyle::AlphaUpper => {
-                                    let ch = ((counter as u8 - 1) + b'A') as char;
-                                    let n = ch.to_string();
-                                    self.push_raw(&n)?;
-                                    self.push_raw(". ")?;
-                                    self.prefix.push("   ".to_string());
-                                }
-                                ListStyle::RomanLower => {
-                                    let n = roman::to(counter.try_into().unwrap_or(1))
-                                        .unwrap_or_default()
-                                        .to_lowercase();
-                                    self.push_raw(&n)?;
-                                    self.push_raw(". ")?;
-                                    let w = n.len() + 2;
-                                    self.prefix.push(" ".repeat(w));
-                                }
-                                ListStyle::RomanUpper => {
-                                    let n = roman::to(counter.try_into().unwrap_or(1))
-                                        .unwrap_or_default()
-                                        .to_uppercase();
-                                    self.push_raw(&n)?;
-                                    self.push_raw(". ")?;
-                                    let w = n.len() + 2;
-                                    self.prefix.push(" ".repeat(w));
+                    match container {
+                        // ---- Block containers ----
+                        Container::Para => {
+                            if is_open {
+                                self.blankline(out)?;
+                                self.para_start = true;
+                            } else {
+                                if !self.pending_word.is_empty() {
+                                    self.commit_word(false, out)?;
                                 }
-                                ListStyle::ParenParen => {
-                                    let n = counter.to_string();
-                                    self.push_raw("(")?;
-                                    self.push_raw(&n)?;
-                                    self.push_raw(") ")?;
-                                    let w = n.len() + 3;
-                                    self.prefix.push(" ".repeat(w));
+                                // A hard break at the end has written the last
+                                // line already.
+                                if self.pending_line.is_empty() {
+                                    self.hard_break = false;
+                                } else {
+                                    self.wrap(out)?;
                                 }
+                                self.para_text = None;
+                                self.need_blankline = true;
                             }
-                            if style != ListStyle::Task {
-                                self.list_item_start = true;
-                            }
-                        } else {
-                            if !self.pending_line.is_empty() {
+                        }
+                        Container::Heading => {
+                            if is_open {
+                                self.blankline(out)?;
+                                self.apply_prefix();
+                                // Extract heading level from source (e.g. "##" => level 2)
+                                let src = self.src(event);
+                                let level = src.chars().take_while(|c| *c == '#').count();
+                                self.heading_level = level;
+                                self.push_raw(&"#".repeat(level))?;
+                                self.push_raw(" ")?;
+                                self.prefix.push(" ".repeat(level + 1));
+                            } else {
+                                if !self.pending_word.is_empty() {
+                                    self.commit_word(false, out)?;
+                                }
                                 self.wrap(out)?;
+                                self.prefix.pop();
                                 self.need_blankline = true;
+                                self.heading_level = 0;
                             }
-                            self.prefix.pop();
                         }
-                    }
-                    "table" => {
-                        if is_open {
-                            self.table_data = Some(TableData::new());
-                            self.no_wrap = true;
-                        } else {
-                            if self.table_data.is_some() {
-                                let td = self.table_data.take().unwrap();
-                                self.render_table(td, out)?;
+                        Container::BlockQuote => {
+                            if is_open {
+                                self.blankline(out)?;
+                                self.prefix.push("> ".to_string());
+                            } else {
+                                self.prefix.pop();
                             }
-                            self.no_wrap = false;
-                            self.need_blankline = true;
                         }
-                    }
-                    "row" => {
-                        if is_open {
-                            if let Some(ref mut td) = self.table_data {
-                                td.current_row_cells.clear();
-                                td.current_row_is_separator = false;
-                                td.current_row_alignments.clear();
-                            }
-                        } else if let Some(ref mut td) = self.table_data {
-                            if td.current_row_is_separator {
-                                let alignments = std::mem::take(&mut td.current_row_alignments);
-                                td.rows.push(TableRow::Separator(alignments));
+                        Container::List(styles) => {
+                            if is_open {
+                                self.blankline(out)?;
+                                let style =
+                                    styles.first().copied().unwrap_or(ListStyle::Bullet('-'));
+                                // The source of the list is its first marker.
+                                let start = style.number(&self.src(event));
+                                let style = self.output_list_style(style);
+                                self.previous_list = None;
+                                self.list_style_stack.push(style);
+                                list_counter.push((start, 0));
+                                let layout = self.lookahead.lists.pop_front().unwrap_or_default();
+                                self.list_tight.push(match self.list_spacing {
+                                    ListSpacing::Preserve => !layout.loose,
+                                    ListSpacing::Tight => layout.single_blocks,
+                                    ListSpacing::Loose => false,
+                                    ListSpacing::Auto => layout.single_paragraphs,
+                                });
                             } else {
-                                let cells = std::mem::take(&mut td.current_row_cells);
-                                td.rows.push(TableRow::Data(cells));
+                                self.previous_list = self.list_style_stack.pop();
+                                list_counter.pop();
+                                self.list_tight.pop();
                             }
                         }
-                    }
-                    "cell" => {
-                        if is_open {
-                            if let Some(ref mut td) = self.table_data {
-                                td.current_cell_content.clear();
-                                self.pending_line.clear();
-                                self.pending_word.clear();
-                                self.space_after_pending_word = false;
+                        Container::ListItem(_) => {
+                            if is_open {
+                                let first =
+                                    list_counter.last().is_none_or(|(_, count)| *count == 0);
+                                if self.list_tight.last() == Some(&true) {
+                                    self.need_blankline = false;
+                                } else if !first && self.have_content {
+                                    self.need_blankline = true;
+                                }
+                                self.blankline(out)?;
+                                self.apply_prefix();
+                                let style = self
+                                    .list_style_stack
+                                    .last()
+                                    .copied()
+                                    .unwrap_or(ListStyle::Bullet('-'));
+                                let (start, count) = list_counter.last_mut().unwrap();
+                                *count += 1;
+                                let counter = match self.list_numbering {
+                                    ListNumbering::Sequential => *start + *count - 1,
+                                    ListNumbering::Repeat => *start,
+                                    ListNumbering::Preserve => style.number(&self.src(event)),
+                                };
+
+                                let marker = match style {
+                                    ListStyle::Bullet(bullet) => bullet.to_string(),
+                                    ListStyle::Task(_) => {
+                                        // marker and prefix emitted by checkbox_* event
+                                        String::new()
+                                    }
+                                    ListStyle::Definition => ":".to_string(),
+                                    ListStyle::Ordered(enumerator, delimiter) => {
+                                        delimiter.mark(&match enumerator {
+                                            Enumerator::Decimal => counter.to_string(),
+                                            Enumerator::AlphaLower => {
+                                                alpha_counter(counter).to_string()
+                                            }
+                                            Enumerator::AlphaUpper => alpha_counter(counter)
+                                                .to_ascii_uppercase()
+                                                .to_string(),
+                                            Enumerator::RomanLower => {
+                                                roman_counter(counter).to_lowercase()
+                                            }
+                                            Enumerator::RomanUpper => roman_counter(counter),
+                                        })
+                                    }
+                                };
+                                if !marker.is_empty() {
+                                    self.push_raw(&marker)?;
+                                    self.push_raw(" ")?;
+                                    // Continuation lines are aligned with the
+                                    // content after the marker.
+                                    self.prefix.push(" ".repeat(marker.width() + 1));
+                                    self.list_item_start = true;
+                                }
+                            } else {
+                                if !self.pending_line.is_empty() {
+                                    self.wrap(out)?;
+                                    self.need_blankline = true;
+                                }
+                                self.list_item_start = false;
+                                self.prefix.pop();
                             }
-                        } else if self.table_data.is_some() {
-                            if !self.pending_word.is_empty() {
-                                self.commit_word(false, out)?;
+                        }
+                        Container::Table => {
+                            if is_open {
+                                self.table_data = Some(TableData::new());
+                                self.no_wrap = true;
+                            } else {
+                                if self.table_data.is_some() {
+                                    let td = self.table_data.take().unwrap();
+                                    self.render_table(td, out)?;
+                                }
+                                self.no_wrap = false;
+                                self.need_blankline = true;
                             }
-                            let content = std::mem::take(&mut self.pending_line);
-                            self.table_data.as_mut().unwrap().current_row_cells.push(
-                                TableCellData {
-                                    content: content.trim_end().to_string(),
-                                },
-                            );
-                            self.pending_line.clear();
-                            self.space_after_pending_word = false;
                         }
-                    }
-                    "caption" => {
-                        if is_open {
-                            // Caption comes after -table. Render ^ prefix like heading.
-                            self.pending_line.clear();
-                            self.pending_word.clear();
-                            self.space_after_pending_word = false;
-                            self.apply_prefix();
-                            self.push_raw("^ ")?;
-                            self.prefix.push("  ".to_string());
-                        } else {
-                            if !self.pending_word.is_empty() {
-                                self.commit_word(false, out)?;
+                        Container::Row => {
+                            if is_open {
+                                if let Some(ref mut td) = self.table_data {
+                                    td.current_row_cells.clear();
+                                    td.current_row_is_separator = false;
+                                    td.current_row_alignments.clear();
+                                }
+                            } else if let Some(ref mut td) = self.table_data {
+                                if td.current_row_is_separator {
+                                    let alignments = std::mem::take(&mut td.current_row_alignments);
+                                    td.rows.push(TableRow::Separator(alignments));
+                                } else {
+                                    let cells = std::mem::take(&mut td.current_row_cells);
+                                    td.rows.push(TableRow::Data(cells));
+                                }
                             }
-                            self.wrap(out)?;
-                            self.prefix.pop();
-                            self.need_blankline = true;
                         }
-                    }
-                    "code_block" => {
-                        if is_open {
-                            self.blankline(out)?;
-                            self.apply_prefix();
-                            self.push_raw("```")?;
-                            self.raw = true;
-                            self.code_block_need_lang = true;
-                            // Don't wrap yet — language may follow
-                        } else {
-                            // Flush any pending content line first
-                            if !self.pending_line.is_empty() || !self.pending_word.is_empty() {
+                        Container::Cell => {
+                            if is_open {
+                                if let Some(ref mut td) = self.table_data {
+                                    td.current_cell_content.clear();
+                                    self.pending_line.clear();
+                                    self.pending_word.clear();
+                                    self.space_after_pending_word = false;
+                                }
+                            } else if self.table_data.is_some() {
+                                if !self.pending_word.is_empty() {
+                                    self.commit_word(false, out)?;
+                                }
+                                let content = std::mem::take(&mut self.pending_line);
+                                self.table_data.as_mut().unwrap().current_row_cells.push(
+                                    TableCellData {
+                                        content: content.trim_end().to_string(),
+                                    },
+                                );
+                                self.pending_line.clear();
+                                self.space_after_pending_word = false;
+                            }
+                        }
+                        Container::Caption => {
+                            if is_open {
+                                // Caption comes after -table. Render ^ prefix like heading.
+                                self.pending_line.clear();
+                                self.pending_word.clear();
+                                self.space_after_pending_word = false;
+                                self.apply_prefix();
+                                self.push_raw("^ ")?;
+                                self.prefix.push("  ".to_string());
+                            } else {
                                 if !self.pending_word.is_empty() {
                                     self.commit_word(false, out)?;
                                 }
                                 self.wrap(out)?;
+                                self.prefix.pop();
+                                self.need_blankline = true;
                             }
-                            self.apply_prefix();
-                            self.push_raw("```")?;
-                            self.wrap(out)?;
-                            self.need_blankline = true;
-                            self.raw = false;
-                            self.code_block_need_lang = false;
                         }
-                    }
-                    "footnote" => {
-                        if is_open {
-                            // note_label event follows; we'll emit marker there
-                        } else {
-                            self.prefix.pop();
+                        Container::CodeBlock => {
+                            if is_open {
+                                self.blankline(out)?;
+                                self.code_block = Some(CodeBlockData::default());
+                            } else if let Some(code) = self.code_block.take() {
+                                self.render_code_block(code, out)?;
+                                self.need_blankline = true;
+                            }
                         }
-                    }
-                    "div" => {
-                        if is_open {
-                            self.blankline(out)?;
-                            self.apply_prefix();
-                            self.push_raw("::: ")?;
-                            self.div_needs_class = true;
-                            // class event follows; wrap happens after class
-                        } else {
-                            // Write closing :::
-                            self.apply_prefix();
-                            self.push_raw(":::")?;
-                            self.wrap(out)?;
-                            // The closing ::: is a block boundary, not content.
-                            // Reset have_content so that a trailing blankline
-                            // event does not produce an extra blank line.
-                            self.have_content = false;
-                            self.need_blankline = true;
+                        Container::Footnote => {
+                            if is_open {
+                                // note_label event follows; we'll emit marker there
+                            } else {
+                                self.prefix.pop();
+                            }
                         }
-                    }
-                    "block_attributes" => {
-                        if is_open {
-                            self.in_block_attrs = true;
-                            self.attr.reset();
-                        } else {
-                            // Render accumulated block attributes
-                            self.apply_prefix();
-                            self.push_word("{")?;
-                            self.commit_word(true, out)?;
-                            let attr_snapshot = self.attr.clone();
-                            self.render_attr(&attr_snapshot, out)?;
-                            self.push_word("}")?;
-                            self.commit_word(true, out)?;
-                            self.wrap(out)?;
-                            // Don't set need_blankline — block attributes attach
-                            // to the next element without a blank line.
-                            self.attr.reset();
-                            self.in_block_attrs = false;
+                        Container::Div => {
+                            if is_open {
+                                self.blankline(out)?;
+                                self.apply_prefix();
+                                let length = self.lookahead.div_fences.pop_front().unwrap_or(3);
+                                let fence = ":".repeat(length);
+                                self.push_raw(&fence)?;
+                                self.div_fences.push(fence);
+                                self.div_needs_class = true;
+                            } else {
+                                self.apply_prefix();
+                                let fence = self.div_fences.pop().unwrap_or_default();
+                                self.push_raw(&fence)?;
+                                self.wrap(out)?;
+                                // The closing ::: is a block boundary, not content.
+                                // Reset have_content so that a trailing blankline
+                                // event does not produce an extra blank line.
+                                self.have_content = false;
+                                self.need_blankline = true;
+                            }
                         }
-                    }
-                    "reference_definition" => {
-                        if is_open {
-                            self.in_ref_def = true;
-                        } else {
-                            // Render the buffered URL
-                            if !self.ref_def_url.is_empty() {
-                                let key_width = self.pending_line.width();
-                                let url_width = self.ref_def_url.len();
-                                if key_width + 1 + url_width > self.max_cols {
-                                    // URL won't fit on the same line as the key
-                                    self.wrap(out)?;
-                                    self.apply_prefix();
-                                    self.pending_line.push_str(&self.ref_def_url);
-                                } else {
-                                    self.pending_line.push(' ');
-                                    self.pending_line.push_str(&self.ref_def_url);
-                                }
-                                self.ref_def_url.clear();
+                        Container::BlockAttributes => {
+                            if is_open {
+                                self.in_block_attrs = true;
+                                self.attr.reset();
+                            } else {
+                                // Render accumulated block attributes
+                                self.apply_prefix();
+                                self.push_word("{")?;
+                                self.commit_word(true, out)?;
+                                let attr_snapshot = self.attr.clone();
+                                self.render_attr(&attr_snapshot, out)?;
+                                self.push_word("}")?;
+                                self.commit_word(true, out)?;
+                                self.wrap(out)?;
+                                // Don't set need_blankline — block attributes attach
+                                // to the next element without a blank line.
+                                self.attr.reset();
+                                self.in_block_attrs = false;
                             }
-                            if !self.pending_word.is_empty() {
-                                self.commit_word(false, out)?;
+                        }
+                        Container::ReferenceDefinition => {
+                            if is_open {
+                                self.in_ref_def = true;
+                            } else {
+                                // Render the buffered URL
+                                if !self.ref_def_url.is_empty() {
+                                    let key_width = self.pending_line.width();
+                                    let url_width = self.ref_def_url.len();
+                                    if key_width + 1 + url_width > self.max_cols {
+                                        // URL won't fit on the same line as the key
+                                        self.wrap(out)?;
+                                        self.apply_prefix();
+                                        self.pending_line.push_str(&self.ref_def_url);
+                                    } else {
+                                        self.pending_line.push(' ');
+                                        self.pending_line.push_str(&self.ref_def_url);
+                                    }
+                                    self.ref_def_url.clear();
+                                }
+                                if !self.pending_word.is_empty() {
+                                    self.commit_word(false, out)?;
+                                }
+                                self.wrap(out)?;
+                                self.prefix.pop();
+                                self.need_blankline = true;
+                                self.in_ref_def = false;
                             }
-                            self.wrap(out)?;
-                            self.prefix.pop();
-                            self.need_blankline = true;
-                            self.in_ref_def = false;
                         }
-                    }
 
-                    // ---- Inline containers ----
-                    "strong" => {
-                        if is_open {
-                            self.push_word("{*")?;
-                        } else {
-                            self.push_word("*}")?;
-                        }
-                    }
-                    "emph" => {
-                        if is_open {
-                            self.push_word("{_")?;
-                        } else {
-                            self.push_word("_}")?;
-                        }
-                    }
-                    "subscript" => {
-                        if is_open {
-                            self.push_word("{~")?;
-                        } else {
-                            self.push_word("~}")?;
-                        }
-                    }
-                    "superscript" => {
-                        if is_open {
-                            self.push_word("{^")?;
-                        } else {
-                            self.push_word("^}")?;
+                        // ---- Inline containers ----
+                        Container::Strong
+                        | Container::Emph
+                        | Container::Subscript
+                        | Container::Superscript => {
+                            let delimiter = bare_delimiter(container).unwrap_or('_');
+                            if is_open {
+                                let bare = self.lookahead.bare_inlines.pop_front().unwrap_or(false)
+                                    && self.emphasis == Emphasis::Bare;
+                                self.bare_stack.push(bare);
+                                if !bare {
+                                    self.push_word("{")?;
+                                }
+                                self.push_word(&delimiter.to_string())?;
+                            } else {
+                                self.push_word(&delimiter.to_string())?;
+                                if !self.bare_stack.pop().unwrap_or(false) {
+                                    self.push_word("}")?;
+                                }
+                            }
                         }
-                    }
-                    "insert" => {
-                        if is_open {
-                            self.push_word("{+")?;
-                        } else {
-                            self.push_word("+}")?;
+                        Container::Insert => {
+                            if is_open {
+                                self.push_word("{+")?;
+                            } else {
+                                self.push_word("+}")?;
+                            }
                         }
-                    }
-                    "delete" => {
-                        if is_open {
-                            self.push_word("{-")?;
-                        } else {
-                            self.push_word("-}")?;
+                        Container::Delete => {
+                            if is_open {
+                                self.push_word("{-")?;
+                            } else {
+                                self.push_word("-}")?;
+                            }
                         }
-                    }
-                    "mark" => {
-                        if is_open {
-                            self.push_word("{=")?;
-                        } else {
-                            self.push_word("=}")?;
+                        Container::Mark => {
+                            if is_open {
+                                self.push_word("{=")?;
+                            } else {
+                                self.push_word("=}")?;
+                            }
                         }
-                    }
-                    "span" => {
-                        if is_open {
-                            self.push_word("[")?;
-                        } else {
-                            self.push_word("]")?;
+                        Container::Span => {
+                            if is_open {
+                                self.push_word("[")?;
+                            } else {
+                                self.push_word("]")?;
+                            }
                         }
-                    }
-                    "linktext" => {
-                        if is_open {
-                            self.push_word("[")?;
-                            self.pending_link_close = true;
+                        Container::LinkText => {
+                            if is_open {
+                                self.push_word("[")?;
+                                self.pending_link_close = true;
+                            }
+                            // close handled by +destination or +reference
                         }
-                        // close handled by +destination or +reference
-                    }
-                    "imagetext" => {
-                        if is_open {
-                            // image_marker event already emitted "!"
-                            self.push_word("[")?;
+                        Container::ImageText => {
+                            if is_open {
+                                // image_marker event already emitted "!"
+                                self.push_word("[")?;
+                            }
                         }
-                    }
-                    "destination" => {
-                        if is_open {
-                            self.pending_link_close = false;
-                            if self.pending_word == ")" {
-                                // ) from a previous -destination (image-in-link
-                                // pattern) — combine into )]( and wrap first.
-                                self.pending_word.clear();
-                                if !self.pending_line.is_empty() {
-                                    self.wrap(out)?;
+                        Container::Destination => {
+                            if is_open {
+                                self.pending_link_close = false;
+                                if self.pending_word == ")" {
+                                    // ) from a previous -destination (image-in-link
+                                    // pattern) — combine into )]( and wrap first.
+                                    self.pending_word.clear();
+                                    if !self.pending_line.is_empty() {
+                                        self.wrap(out)?;
+                                    }
+                                    self.push_word(")](")?;
+                                } else {
+                                    self.push_word("](")?;
                                 }
-                                self.push_word(")](")?;
+                                self.commit_word(false, out)?;
+                                self.in_destination = true;
                             } else {
-                                self.push_word("](")?;
+                                self.in_destination = false;
+                                if !self.pending_word.is_empty() {
+                                    self.commit_word(false, out)?;
+                                }
+                                self.push_word(")")?;
                             }
-                            self.commit_word(false, out)?;
-                            self.in_destination = true;
-                        } else {
-                            self.in_destination = false;
-                            if !self.pending_word.is_empty() {
+                        }
+                        Container::Reference => {
+                            if is_open {
+                                self.pending_link_close = false;
+                                self.push_word("][")?;
                                 self.commit_word(false, out)?;
+                            } else {
+                                self.push_word("]")?;
                             }
-                            self.push_word(")")?;
-                        }
-                    }
-                    "reference" => {
-                        if is_open {
-                            self.pending_link_close = false;
-                            self.push_word("][")?;
-                            self.commit_word(false, out)?;
-                        } else {
-                            self.push_word("]")?;
                         }
-                    }
-                    "verbatim" => {
-                        if is_open {
-                            // Extract opening backtick sequence from source
-                            let src = self.src(event);
-                            let ticks: String = src.chars().take_while(|c| *c == '`').collect();
-                            self.verbatim_ticks = ticks.clone();
-                            self.push_word(&ticks)?;
-                            // Don't set raw — inline verbatim content should wrap like normal text
-                        } else {
-                            let ticks = self.verbatim_ticks.clone();
-                            self.push_word(&ticks)?;
-                        }
-                    }
-                    "inline_math" => {
-                        if is_open {
-                            self.push_word("$`")?;
-                            self.raw = true;
-                        } else {
-                            self.raw = false;
-                            self.push_word("`")?;
+                        Container::Verbatim => {
+                            if is_open {
+                                self.verbatim = Some(String::new());
+                            } else if let Some(content) = self.verbatim.as_mut().map(std::mem::take)
+                            {
+                                self.render_verbatim(&content, out)?;
+                                self.verbatim = None;
+                            }
                         }
-                    }
-                    "display_math" => {
-                        if is_open {
-                            self.push_word("$$`")?;
-                            self.raw = true;
-                        } else {
-                            self.raw = false;
-                            self.push_word("`")?;
+                        Container::InlineMath => {
+                            if is_open {
+                                self.push_word("$`")?;
+                                self.raw = true;
+                            } else {
+                                self.raw = false;
+                                self.push_word("`")?;
+                            }
                         }
-                    }
-                    "url" | "email" => {
-                        if is_open {
-                            self.push_word("<")?;
-                        } else {
-                            self.push_word(">")?;
+                        Container::DisplayMath => {
+                            if is_open {
+                                self.push_word("$$`")?;
+                                self.raw = true;
+                            } else {
+                                self.raw = false;
+                                self.push_word("`")?;
+                            }
                         }
-                    }
-                    "attributes" => {
-                        if is_open {
-                            self.in_inline_attrs = true;
-                            self.attr.reset();
-                        } else {
-                            // Render accumulated inline attributes.
-                            // If there's a pending word, the attribute attaches to it:
-                            // commit the word without trailing space, then no space
-                            // before {.
-                            // If pending_word is empty, the preceding text ended with
-                            // a space (e.g. standalone comment): keep the space before {.
-                            if !self.pending_word.is_empty() {
-                                self.commit_word(false, out)?;
-                                self.space_after_pending_word = false;
+                        Container::Url | Container::Email => {
+                            if is_open {
+                                self.push_word("<")?;
+                            } else {
+                                self.push_word(">")?;
                             }
-                            self.push_word("{")?;
-                            self.commit_word(true, out)?;
-                            let attr_snapshot = self.attr.clone();
-                            self.render_attr(&attr_snapshot, out)?;
-                            self.push_word("}")?;
-                            self.attr.reset();
-                            self.in_inline_attrs = false;
                         }
-                    }
-                    "single_quoted" => {
-                        if is_open {
-                            self.push_word("{'")?;
-                        } else {
-                            self.push_word("'}")?;
+                        Container::Attributes => {
+                            if is_open {
+                                self.in_inline_attrs = true;
+                                self.attr.reset();
+                            } else {
+                                // Render accumulated inline attributes.
+                                // If there's a pending word, the attribute attaches to it:
+                                // { joins the word, so no line break comes between them.
+                                // If pending_word is empty, the preceding text ended with
+                                // a space (e.g. standalone comment): keep the space before {.
+                                self.push_word("{")?;
+                                self.commit_word(true, out)?;
+                                let attr_snapshot = self.attr.clone();
+                                self.render_attr(&attr_snapshot, out)?;
+                                self.push_word("}")?;
+                                self.attr.reset();
+                                self.in_inline_attrs = false;
+                            }
                         }
-                    }
-                    "double_quoted" => {
-                        if is_open {
-                            self.push_word("{\"")?;
-                        } else {
-                            self.push_word("\"}")?;
+                        Container::SingleQuoted | Container::DoubleQuoted => {
+                            let single = *container == Container::SingleQuoted;
+                            if is_open {
+                                let bare = self.lookahead.bare_inlines.pop_front().unwrap_or(false);
+                                self.bare_stack.push(bare);
+                            }
+                            let bare = self.bare_stack.last() == Some(&true);
+                            if !is_open {
+                                self.bare_stack.pop();
+                            }
+                            let quote = if single { "'" } else { "\"" };
+                            let quote = match (self.smart_punctuation, is_open) {
+                                (SmartPunctuation::Preserve, _) => self.src(event),
+                                (SmartPunctuation::Minimal, _) if bare => quote.to_string(),
+                                (SmartPunctuation::Minimal, true) => format!("{{{}", quote),
+                                (SmartPunctuation::Minimal, false) => format!("{}}}", quote),
+                                (SmartPunctuation::Unicode, true) if single => "\u{2018}".into(),
+                                (SmartPunctuation::Unicode, false) if single => "\u{2019}".into(),
+                                (SmartPunctuation::Unicode, true) => "\u{201c}".into(),
+                                (SmartPunctuation::Unicode, false) => "\u{201d}".into(),
+                            };
+                            self.push_word(&quote)?;
                         }
                     }
-                    _ => {
-                        log::warn!("Unknown container event: {}", annot);
-                    }
                 }
-            } else {
-                // Leaf events
-                match annot.as_str() {
-                    "str" => {
-                        let text = self.src(event);
-                        if self.raw {
-                            // First str in code_block without language: close the ``` line
-                            if self.code_block_need_lang {
-                                self.wrap(out)?;
-                                self.code_block_need_lang = false;
+                EventKind::Str => {
+                    let text = self.src(event);
+                    if let Some(code) = &mut self.code_block {
+                        code.content.push_str(&text);
+                    } else if let Some(verbatim) = &mut self.verbatim {
+                        verbatim.push_str(&text);
+                    } else if self.raw {
+                        for char in text.chars() {
+                            if char != '\n' {
+                                self.push_word(char.to_string().as_str())?;
+                                continue;
                             }
-                            for char in text.chars() {
-                                if char != '\n' {
-                                    self.push_word(char.to_string().as_str())?;
-                                    continue;
-                                }
-                                if !self.pending_word.is_empty() {
-                                    self.commit_word(false, out)?;
-                                }
-                                self.wrap(out)?;
+                            if !self.pending_word.is_empty() {
+                                self.commit_word(false, out)?;
                             }
-                        } else if self.code_block_need_lang {
-                            // This str after +code_block is the language
-                            // Actually language comes via code_language event, not str
-                            // Handle as normal str
-                            self.emit_str_words(&text, out)?;
-                        } else {
-                            self.emit_str_words(&text, out)?;
-                        }
-                    }
-                    "soft_break" => {
-                        if !self.pending_word.is_empty() {
-                            self.commit_word(true, out)?;
+                            self.wrap(out)?;
                         }
-                        self.wrap(out)?;
+                    } else {
+                        self.emit_str_words(&text, out)?;
                     }
-                    "hard_break" => {
-                        if !self.pending_word.is_empty() {
-                            self.commit_word(false, out)?;
-                        }
-                        self.wrap(out)?;
+                }
+                EventKind::SoftBreak => {
+                    if let Some(verbatim) = &mut self.verbatim {
+                        verbatim.push('\n');
+                    } else {
+                        self.soft_break(out)?;
                     }
-                    "blankline" => {
-                        // Source blank line: output a blank line if we've written
-                        // content since the last blank line. This preserves explicit
-                        // blank lines while collapsing consecutive ones.
-                        if self.have_content {
-                            self.need_blankline = true;
-                            self.blankline(out)?;
-                        }
+                }
+                EventKind::HardBreak => {
+                    if !self.pending_word.is_empty() {
+                        self.commit_word(false, out)?;
                     }
-                    "thematic_break" => {
-                        self.blankline(out)?;
-                        self.apply_prefix();
-                        self.push_raw("* * *")?;
-                        let column = self.pending_line.width_cjk();
-                        if column < self.max_cols {
-                            self.push_raw(" *".repeat((self.max_cols - column) / 2).as_str())?;
-                        }
-                        self.wrap(out)?;
+                    self.wrap(out)?;
+                    self.hard_break = true;
+                }
+                EventKind::Blankline => {
+                    // Source blank line: output a blank line if we've written
+                    // content since the last blank line. This preserves explicit
+                    // blank lines while collapsing consecutive ones. Blank lines
+                    // directly in tight lists are dropped, there can be no others.
+                    if self.have_content && self.list_tight.last() != Some(&true) {
                         self.need_blankline = true;
-                    }
-                    "escape" => {
-                        self.push_word("\\")?;
-                    }
-                    "non_breaking_space" => {
-                        self.push_word(" ")?;
-                    }
-                    "footnote_reference" => {
-                        // src() now returns the complete [^label] text
-                        let text = self.src(event);
-                        self.push_word(&text)?;
-                    }
-                    "code_language" => {
-                        let lang = self.src(event);
-                        if !lang.is_empty() {
-                            self.push_raw(" ")?;
-                            self.push_raw(&lang)?;
-                        }
-                        self.code_block_need_lang = false;
-                        // End the ``` language line
-                        self.wrap(out)?;
-                    }
-                    "note_label" => {
-                        // Footnote definition label
-                        let label = self.src(event);
                         self.blankline(out)?;
-                        self.apply_prefix();
-                        self.push_raw("[^")?;
-                        self.push_raw(&label)?;
-                        self.push_raw("]:")?;
-                        self.wrap(out)?;
-                        self.prefix.push("  ".to_string());
-                        self.need_blankline = false;
-                    }
-                    "checkbox_checked" => {
-                        self.apply_prefix();
-                        self.push_raw("- [x] ")?;
-                        self.prefix.push("      ".to_string());
-                        self.list_item_start = true;
-                    }
-                    "checkbox_unchecked" => {
-                        self.apply_prefix();
-                        self.push_raw("- [ ] ")?;
-                        self.prefix.push("      ".to_string());
-                        self.list_item_start = true;
                     }
-                    "image_marker" => {
-                        if !self.pending_word.is_empty() {
-                            // Commit the preceding word. If it's a punctuation
-                            // marker like "[" from linktext, don't add trailing
-                            // space — "![" should follow immediately. Otherwise
-                            // add space (normal word separation).
-                            let space =
-                                !matches!(self.pending_word.as_str(), "[" | "(" | "![" | "\"");
-                            self.commit_word(space, out)?;
+                }
+                EventKind::ThematicBreak => {
+                    let mut thematic_break = self.thematic_break;
+                    // A spaced break of the bullet of the list right before it
+                    // would be an item of that list, the other mark is not.
+                    if let Some(ListStyle::Bullet(bullet) | ListStyle::Task(bullet)) =
+                        self.previous_list.take()
+                    {
+                        if thematic_break.spaced && bullet == thematic_break.mark {
+                            thematic_break.mark = if bullet == '*' { '-' } else { '*' };
                         }
-                        self.push_word("!")?;
-                    }
-                    "open_marker" => {
-                        // "{" before emphasis etc — already handled by +strong etc.
-                    }
-                    "symb" => {
-                        // src() returns ":name:" (already has both colons)
-                        let text = self.src(event);
-                        self.push_word(&text)?;
-                    }
-
-                    // Smart punctuation
-                    "left_single_quote" => {
-                        self.push_word("{'")?;
                     }
-                    "right_single_quote" => {
-                        self.push_word("'}")?;
-                    }
-                    "left_double_quote" => {
-                        self.push_word("{\"")?;
-                    }
-                    "right_double_quote" => {
-                        self.push_word("\"}")?;
-                    }
-                    "en_dash" => {
-                        self.push_word("--")?;
+                    self.blankline(out)?;
+                    // After the marker of a list item, the break would take
+                    // the marker in.
+                    if self.list_item_start {
+                        self.wrap(out)?;
+                        self.list_item_start = false;
                     }
-                    "em_dash" => {
-                        self.push_word("---")?;
+                    self.apply_prefix();
+                    let width = self
+                        .thematic_break_fill
+                        .then(|| self.max_cols.saturating_sub(self.pending_line.width_cjk()));
+                    self.push_raw(&thematic_break.render(width))?;
+                    self.wrap(out)?;
+                    self.need_blankline = true;
+                }
+                EventKind::Escape => {
+                    let escaped = &self.source[event.endpos + 1..];
+                    if !redundant_escape(escaped, self.table_data.is_some())
+                        || in_braces(&self.source[..event.startpos])
+                    {
+                        self.push_word("\\")?;
                     }
-                    "ellipses" => {
-                        self.push_word("...")?;
+                }
+                EventKind::NonBreakingSpace => {
+                    self.push_word(" ")?;
+                }
+                EventKind::FootnoteReference => {
+                    // src() now returns the complete [^label] text
+                    let text = self.src(event);
+                    self.push_word(&text)?;
+                }
+                EventKind::CodeLanguage => {
+                    let lang = self.src(event);
+                    if let Some(code) = &mut self.code_block {
+                        code.info = lang;
                     }
+                }
+                EventKind::NoteLabel => {
+                    // Footnote definition label
+                    let label = self.src(event);
+                    self.blankline(out)?;
+                    self.apply_prefix();
+                    self.push_raw("[^")?;
+                    self.push_raw(&label)?;
+                    self.push_raw("]:")?;
+                    self.wrap(out)?;
+                    self.prefix.push("  ".to_string());
+                    self.need_blankline = false;
+                }
+                EventKind::CheckboxChecked => {
+                    self.apply_prefix();
+                    self.push_raw(&format!("{} [x] ", self.task_bullet()))?;
+                    self.prefix.push("      ".to_string());
+                    self.list_item_start = true;
+                }
+                EventKind::CheckboxUnchecked => {
+                    self.apply_prefix();
+                    self.push_raw(&format!("{} [ ] ", self.task_bullet()))?;
+                    self.prefix.push("      ".to_string());
+                    self.list_item_start = true;
+                }
+                EventKind::ImageMarker => {
+                    if !self.pending_word.is_empty() {
+                        // Commit the preceding word. If it's a punctuation
+                        // marker like "[" from linktext, don't add trailing
+                        // space — "![" should follow immediately. Otherwise
+                        // add space (normal word separation).
+                        let space = !matches!(self.pending_word.as_str(), "[" | "(" | "![" | "\"");
+                        self.commit_word(space, out)?;
+                    }
+                    self.push_word("!")?;
+                }
+                EventKind::OpenMarker => {
+                    // "{" before emphasis etc — already handled by +strong etc.
+                }
+                EventKind::Symb => {
+                    // src() returns ":name:" (already has both colons)
+                    let text = self.src(event);
+                    self.push_word(&text)?;
+                }
 
-                    // Table separators
-                    "separator_default" | "separator_left" | "separator_right"
-                    | "separator_center" => {
-                        if let Some(ref mut td) = self.table_data {
-                            let alignment = match annot.as_str() {
-                                "separator_left" => Alignment::Left,
-                                "separator_right" => Alignment::Right,
-                                "separator_center" => Alignment::Center,
-                                _ => Alignment::Unspecified,
-                            };
-                            td.current_row_is_separator = true;
-                            td.current_row_alignments.push(alignment);
+                // Smart punctuation
+                EventKind::LeftSingleQuote
+                | EventKind::RightSingleQuote
+                | EventKind::LeftDoubleQuote
+                | EventKind::RightDoubleQuote
+                | EventKind::EnDash
+                | EventKind::EmDash
+                | EventKind::Ellipses => {
+                    let bare = *kind == EventKind::RightSingleQuote
+                        && self.lookahead.bare_inlines.pop_front().unwrap_or(false);
+                    let text = match self.smart_punctuation {
+                        SmartPunctuation::Unicode => match kind {
+                            EventKind::LeftSingleQuote => "\u{2018}",
+                            EventKind::RightSingleQuote => "\u{2019}",
+                            EventKind::LeftDoubleQuote => "\u{201c}",
+                            EventKind::RightDoubleQuote => "\u{201d}",
+                            EventKind::EnDash => "\u{2013}",
+                            EventKind::EmDash => "\u{2014}",
+                            _ => "\u{2026}",
                         }
-                    }
+                        .to_string(),
+                        SmartPunctuation::Minimal if bare => "'".to_string(),
+                        _ => self.src(event),
+                    };
+                    self.push_word(&text)?;
+                }
 
-                    // Attribute events
-                    "attr_class_marker" => {
-                        self.attr.set_kind(AttrKind::Class);
-                    }
-                    "attr_id_marker" => {
-                        self.attr.set_kind(AttrKind::Id);
-                    }
-                    "attr_equal_marker" => {
-                        // The current pending value was actually a key
-                        self.attr.set_kind(AttrKind::Key);
-                    }
-                    "attr_quote_marker" => {
-                        // quote around value — no action needed, quoting is handled in render_attr
-                    }
-                    "attr_space" => {}
-                    "class" => {
-                        let val = self.src(event);
-                        if self.div_needs_class {
-                            self.push_raw(&val)?;
-                            self.wrap(out)?;
-                            self.need_blankline = true;
-                            self.div_needs_class = false;
-                        } else {
-                            self.attr.pending = Some(AttrKind::Class);
-                            self.attr.parts.push((AttrKind::Class, val.to_string()));
-                            self.attr.pending = None;
-                        }
-                    }
-                    "id" => {
-                        let val = self.src(event);
-                        self.attr.parts.push((AttrKind::Id, val.to_string()));
-                    }
-                    "key" => {
-                        let val = self.src(event);
-                        self.attr.parts.push((AttrKind::Key, val.to_string()));
-                    }
-                    "value" => {
-                        let val = self.src(event);
-                        self.attr.parts.push((AttrKind::Value, val.to_string()));
-                    }
-                    "comment" => {
-                        let val = self.src(event);
-                        // Strip leading/trailing % markers and normalize whitespace
-                        let val = val
-                            .trim_start_matches('%')
-                            .trim_end_matches('%')
-                            .split_whitespace()
-                            .collect::<Vec<_>>()
-                            .join(" ");
-                        self.attr.parts.push((AttrKind::Comment, val));
-                    }
-                    "raw_format" => {
-                        let val = self.src(event);
-                        // Strip surrounding {=...} or =... markers
-                        let format = val
-                            .trim_start_matches('{')
-                            .trim_start_matches('=')
-                            .trim_end_matches('}');
-                        if self.raw {
-                            // Inside code_block, emit =format (no braces)
-                            self.push_raw(" =")?;
-                            self.push_raw(format)?;
-                            self.wrap(out)?;
-                            self.code_block_need_lang = false;
-                        } else {
-                            // Inline raw format after verbatim
-                            self.push_word("{=")?;
-                            self.push_word(format)?;
-                            self.push_word("}")?;
-                        }
+                // Table separators
+                EventKind::SeparatorDefault
+                | EventKind::SeparatorLeft
+                | EventKind::SeparatorRight
+                | EventKind::SeparatorCenter => {
+                    if let Some(ref mut td) = self.table_data {
+                        let alignment = match kind {
+                            EventKind::SeparatorLeft => Alignment::Left,
+                            EventKind::SeparatorRight => Alignment::Right,
+                            EventKind::SeparatorCenter => Alignment::Center,
+                            _ => Alignment::Unspecified,
+                        };
+                        td.current_row_is_separator = true;
+                        td.current_row_alignments.push(alignment);
                     }
+                }
 
-                    "reference_key" => {
-                        let src = self.src(event);
-                        let key = src.trim_start_matches('[').trim_end_matches(']');
-                        self.blankline(out)?;
-                        self.apply_prefix();
-                        self.push_raw("[")?;
-                        self.push_raw(key)?;
-                        self.push_raw("]:")?;
-                        self.ref_def_url.clear();
-                        self.prefix.push(" ".to_string());
+                // Attribute events
+                EventKind::AttrClassMarker => {
+                    self.attr.set_kind(AttrKind::Class);
+                }
+                EventKind::AttrIdMarker => {
+                    self.attr.set_kind(AttrKind::Id);
+                }
+                EventKind::AttrEqualMarker => {
+                    // The current pending value was actually a key
+                    self.attr.set_kind(AttrKind::Key);
+                }
+                EventKind::AttrQuoteMarker => {
+                    // quote around value — no action needed, quoting is handled in render_attr
+                }
+                EventKind::AttrSpace => {}
+                EventKind::Class => {
+                    let val = self.src(event);
+                    if self.div_needs_class {
+                        self.push_raw(" ")?;
+                        self.push_raw(&val)?;
+                        self.end_div_fence(out)?;
+                    } else {
+                        self.attr.pending = Some(AttrKind::Class);
+                        self.attr.parts.push((AttrKind::Class, val.to_string()));
+                        self.attr.pending = None;
                     }
-                    "reference_value" => {
-                        let val = self.src(event);
-                        self.ref_def_url.push_str(&val);
+                }
+                EventKind::Id => {
+                    let val = self.src(event);
+                    self.attr.parts.push((AttrKind::Id, val.to_string()));
+                }
+                EventKind::Key => {
+                    let val = self.src(event);
+                    self.attr.parts.push((AttrKind::Key, val.to_string()));
+                }
+                EventKind::Value => {
+                    let val = self.src(event);
+                    self.attr.parts.push((AttrKind::Value, val.to_string()));
+                }
+                EventKind::Comment => {
+                    let val = self.src(event);
+                    // Strip leading/trailing % markers and normalize whitespace
+                    let val = val
+                        .trim_start_matches('%')
+                        .trim_end_matches('%')
+                        .split_whitespace()
+                        .collect::<Vec<_>>()
+                        .join(" ");
+                    self.attr.parts.push((AttrKind::Comment, val));
+                }
+                EventKind::RawFormat => {
+                    let val = self.src(event);
+                    // Strip surrounding {=...} or =... markers
+                    let format = val
+                        .trim_start_matches('{')
+                        .trim_start_matches('=')
+                        .trim_end_matches('}');
+                    if let Some(code) = &mut self.code_block {
+                        // Inside code_block, emit =format (no braces)
+                        code.info = format!("={}", format);
+                    } else {
+                        // Inline raw format after verbatim
+                        self.push_word("{=")?;
+                        self.push_word(format)?;
+                        self.push_word("}")?;
                     }
+                }
 
-                    _ => {
-                        log::warn!("Unknown leaf event: {}", annot);
-                    }
+                EventKind::ReferenceKey => {
+                    let src = self.src(event);
+                    let key = src.trim_start_matches('[').trim_end_matches(']');
+                    self.blankline(out)?;
+                    self.apply_prefix();
+                    self.push_raw("[")?;
+                    self.push_raw(key)?;
+                    self.push_raw("]:")?;
+                    self.ref_def_url.clear();
+                    self.prefix.push(" ".to_string());
+                }
+                EventKind::ReferenceValue => {
+                    let val = self.src(event);
+                    self.ref_def_url.push_str(&val);
                 }
             }
-
-            i += 1;
         }
 
         // Final flush: ensure all pending content is written
@@ -1375,14 +2292,122 @@ impl<'a> FmtWriter<'a> {
                 continue;
             }
 
+            self.end_word(out)?;
+
+            space = true;
+        }
+        Ok(())
+    }
+
+    fn soft_break<W: std::fmt::Write>(&mut self, out: &mut W) -> std::fmt::Result {
+        if self.wrap_mode == Wrap::Preserve {
             if !self.pending_word.is_empty() {
                 self.commit_word(true, out)?;
+            }
+            self.line_break = true;
+            Ok(())
+        } else if self.in_destination {
+            // Line breaks inside a link destination are not part of it.
+            Ok(())
+        } else {
+            self.end_word(out)?;
+            // Line breaks are kept where joining lines could make the first
+            // line start a block, or `[^` and `]` around them a footnote
+            // reference.
+            self.line_break |= self.para_text.is_some() || self.in_footnote_label();
+            Ok(())
+        }
+    }
+
+    /// Whether the current line has a `[^` which is not closed yet.
+    fn in_footnote_label(&self) -> bool {
+        let line = &self.pending_line;
+        line.rfind("[^")
+            .is_some_and(|start| !line[start..].contains(']'))
+    }
+
+    /// End the opening fence line of a div, with or without a class.
+    fn end_div_fence<W: std::fmt::Write>(&mut self, out: &mut W) -> std::fmt::Result {
+        self.div_needs_class = false;
+        self.wrap(out)?;
+        self.need_blankline = true;
+        Ok(())
+    }
+
+    /// Write inline verbatim `content` between the fewest backticks no run
+    /// in the content is as long as, padded with a space where the content
+    /// starts or ends with a backtick.
+    fn render_verbatim<W: std::fmt::Write>(
+        &mut self,
+        content: &str,
+        out: &mut W,
+    ) -> std::fmt::Result {
+        // A single space separating the content from backticks in the
+        // delimiters is not part of the content.
+        let mut content = content;
+        if content.starts_with(" `") {
+            content = &content[1..];
+        }
+        if content.ends_with("` ") {
+            content = &content[..content.len() - 1];
+        }
+
+        let mut runs = std::collections::HashSet::new();
+        let mut run = 0;
+        for c in content.chars().chain(std::iter::once('\0')) {
+            if c == '`' {
+                run += 1;
+            } else if run > 0 {
+                runs.insert(run);
+                run = 0;
+            }
+        }
+        let ticks = "`".repeat((1..).find(|n| !runs.contains(n)).unwrap_or(1));
+
+        // The padding is part of the word, so that no line starts with it.
+        self.push_word(&ticks)?;
+        if content.starts_with('`') {
+            self.push_word(" ")?;
+        }
+        // Lines are only broken at single spaces and line breaks between
+        // other characters. Other whitespace is content and kept as it is,
+        // except for line breaks, which are written as spaces.
+        let chars: Vec<char> = content.chars().collect();
+        let is_space = |i: usize| matches!(chars.get(i), Some(' ' | '\n'));
+        let mut word = String::new();
+        for (i, &c) in chars.iter().enumerate() {
+            if !is_space(i) || i == 0 || is_space(i - 1) || i + 1 == chars.len() || is_space(i + 1)
+            {
+                word.push(if c == '\n' { ' ' } else { c });
+                continue;
+            }
+            self.push_word(&std::mem::take(&mut word))?;
+            if c == '\n' {
+                self.soft_break(out)?;
             } else {
-                self.space_after_pending_word = true;
+                self.end_word(out)?;
             }
+        }
+        self.push_word(&word)?;
+        if content.ends_with('`') {
+            self.push_word(" ")?;
+        }
+        self.push_word(&ticks)
+    }
 
-            space = true;
+    /// Handle whitespace after the pending word, breaking the line there if
+    /// it ends a sentence in [`Wrap::Sentence`] mode.
+    fn end_word<W: std::fmt::Write>(&mut self, out: &mut W) -> std::fmt::Result {
+        if self.pending_word.is_empty() {
+            self.space_after_pending_word = true;
+            return Ok(());
         }
+        let sentence_end = self.wrap_mode == Wrap::Sentence
+            && self.verbatim.is_none()
+            && self.table_data.is_none()
+            && ends_sentence(&self.pending_word);
+        self.commit_word(true, out)?;
+        self.line_break |= sentence_end;
         Ok(())
     }
 }
@@ -0,0 +1,60 @@
+// SPDX-FileCopyrightText: 2026 Chen Linxuan <me@black-desk.cn>
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Atomic replacement of a file's content.
+
+use std::io::Write;
+
+/// Replace the content of `file` with `content`.
+///
+/// The content is written to a uniquely named temporary file in the same
+/// directory, which then gets the permissions (and on Unix, if possible, the
+/// ownership) of `file` and is renamed over it. Readers see either the old or
+/// the new content, and a crash leaves at most a stray temporary file behind,
+/// which never prevents later runs. Symbolic links are followed, so the link
+/// itself is kept.
+pub fn replace(file: &std::path::Path, content: &str) -> std::io::Result<()> {
+    let file = std::fs::canonicalize(file)?;
+    let metadata = std::fs::metadata(&file)?;
+    let dir = file.parent().unwrap_or(std::path::Path::new("."));
+
+    let mut prefix = std::ffi::OsString::from(".");
+    prefix.push(file.file_name().unwrap_or_default());
+    prefix.push(".");
+
+    let mut temp = tempfile::Builder::new()
+        .prefix(&prefix)
+        .suffix(".djotfmt.tmp")
+        .tempfile_in(dir)?;
+
+    log::trace!("Writing to temporary file {}", temp.path().display());
+
+    temp.write_all(content.as_bytes())?;
+    temp.as_file().set_permissions(metadata.permissions())?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        if let Err(err) =
+            std::os::unix::fs::fchown(temp.as_file(), Some(metadata.uid()), Some(metadata.gid()))
+        {
+            log::warn!(
+                "Cannot preserve the ownership of {}: {}",
+                file.display(),
+                err
+            );
+        }
+    }
+
+    temp.as_file().sync_all()?;
+
+    log::trace!(
+        "Renaming temporary file {} to {}",
+        temp.path().display(),
+        file.display()
+    );
+
+    temp.persist(&file).map_err(|err| err.error)?;
+    Ok(())
+}
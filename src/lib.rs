@@ -2,5 +2,8 @@
 //
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+pub mod ast;
+pub mod config;
 pub mod fmt;
 pub mod parser;
+pub mod verify;
@@ -3,9 +3,12 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
 mod cli;
+mod inplace;
+mod walk;
 
-fn main() -> std::io::Result<()> {
+fn main() -> std::io::Result<std::process::ExitCode> {
     use clap::Parser;
+    use std::io::Write;
     let matches = cli::Cli::parse();
 
     colog::default_builder()
@@ -19,51 +22,186 @@ fn main() -> std::io::Result<()> {
 
     log::trace!("CLI options: {:?}", matches);
 
-    for file in matches.input {
-        log::trace!("Processing file: {}", file.display());
+    let mut resolver = djotfmt::config::ConfigResolver::new();
 
-        let output: &mut dyn std::io::Write = match matches.inplace {
-            false => {
-                log::trace!("Writing to stdout");
-                &mut std::io::stdout()
-            }
-            true => {
-                let swap = file.with_extension("djotfmt.swp");
+    // Set when --check or --diff finds a file that is not formatted.
+    let mut unformatted = false;
+
+    let files = walk::expand_inputs(&matches.input, &matches.include, &matches.exclude)?;
 
-                log::trace!("Writing to swap file {}", swap.display());
+    // Set when any file cannot be processed.
+    let mut failed = false;
 
-                &mut std::fs::File::create_new(swap).expect("Swapping file already exists")
+    let mut tasks = Vec::with_capacity(files.len());
+    for file in files {
+        let mut config = djotfmt::fmt::FmtConfig::default();
+        match resolver.resolve(&file) {
+            Ok(file_config) => file_config.apply(&mut config),
+            Err(err) => {
+                log::error!("{}: {}", file.display(), err);
+                failed = true;
+                continue;
+            }
+        }
+        if let Some(columns) = matches.columns {
+            config.max_cols = columns;
+        }
+        if let Some(wrap) = matches.wrap {
+            config.wrap = wrap;
+        }
+        log::debug!("Format config of {}: {:?}", file.display(), config);
+        tasks.push((file, config));
+    }
+
+    let jobs = match matches.jobs {
+        0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    };
+    format_files(&tasks, jobs, matches.verify, |file, result| {
+        let (input, result) = match result {
+            Ok(result) => result,
+            Err(err) => {
+                log::error!("{}: {}", file.display(), err);
+                failed = true;
+                return Ok(());
             }
         };
 
-        log::trace!("Start render file");
+        if matches.check || matches.diff {
+            if result != input {
+                log::info!("File {} is not formatted", file.display());
+                if matches.diff {
+                    let name = file.display().to_string();
+                    print!(
+                        "{}",
+                        similar::TextDiff::from_lines(&input, &result)
+                            .unified_diff()
+                            .context_radius(3)
+                            .header(&name, &name)
+                    );
+                } else {
+                    println!("{}", file.display());
+                }
+                unformatted = true;
+            }
+            return Ok(());
+        }
 
-        let input = std::fs::read_to_string(file.clone())?;
+        if !matches.inplace {
+            log::trace!("Writing to stdout");
+            return std::io::stdout().write_all(result.as_bytes());
+        }
 
-        let config = djotfmt::fmt::FmtConfig {
-            max_cols: matches.columns,
-        };
+        if result == input {
+            log::trace!("File {} is already formatted", file.display());
+            return Ok(());
+        }
 
-        let result = djotfmt::fmt::format(&input, &config);
-        output.write_all(result.as_bytes())?;
+        if let Err(err) = inplace::replace(file, &result) {
+            log::error!("{}: {}", file.display(), err);
+            failed = true;
+        }
+        Ok(())
+    })?;
 
-        log::trace!("File rendered");
+    if failed {
+        return Ok(std::process::ExitCode::FAILURE);
+    }
 
-        if !matches.inplace {
-            continue;
-        }
+    if unformatted && matches.check {
+        return Ok(std::process::ExitCode::FAILURE);
+    }
 
-        let swap = file.with_extension("djotfmt.swp");
-        assert!(swap.exists(), "Swap file does not exist");
+    Ok(std::process::ExitCode::SUCCESS)
+}
 
-        log::trace!(
-            "Renaming swap file {} to {}",
-            swap.display(),
-            file.display()
-        );
+/// Read and format `file`, verifying the result if `verify` is set.
+///
+/// A panic while formatting is reported as an error of the file, so that the
+/// other files are still formatted.
+fn format_file(
+    file: &std::path::Path,
+    config: &djotfmt::fmt::FmtConfig,
+    verify: bool,
+) -> std::io::Result<(String, String)> {
+    log::trace!("Start render file {}", file.display());
+    let input = std::fs::read_to_string(file)?;
+    let result = std::panic::catch_unwind(|| {
+        let result = djotfmt::fmt::format(&input, config);
+        log::trace!("File {} rendered", file.display());
+        if verify {
+            djotfmt::verify::verify(&input, &result, config).map_err(std::io::Error::other)?;
+            log::trace!("File {} verified", file.display());
+        }
+        Ok(result)
+    })
+    .unwrap_or_else(|panic| {
+        let message = panic
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("unknown error");
+        Err(std::io::Error::other(format!(
+            "formatting panicked: {}",
+            message
+        )))
+    })?;
+    Ok((input, result))
+}
 
-        std::fs::rename(file.with_extension("djotfmt.swp"), file)?;
+/// Read and format every file of `tasks` on `jobs` threads, verifying the
+/// result if `verify` is set, and pass each result to `write`.
+///
+/// Results are passed in the order of `tasks`, whatever order the files are
+/// processed in, so output and error reporting stay deterministic. Each
+/// result is passed as soon as it and the ones before it are ready, so only
+/// the files finished out of order are kept in memory. An error returned by
+/// `write` stops formatting.
+fn format_files(
+    tasks: &[(std::path::PathBuf, djotfmt::fmt::FmtConfig)],
+    jobs: usize,
+    verify: bool,
+    mut write: impl FnMut(&std::path::Path, std::io::Result<(String, String)>) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    if jobs <= 1 || tasks.len() <= 1 {
+        for (file, config) in tasks {
+            write(file, format_file(file, config, verify))?;
+        }
+        return Ok(());
     }
 
-    Ok(())
+    let next = std::sync::atomic::AtomicUsize::new(0);
+    let (sender, receiver) = std::sync::mpsc::channel();
+
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.min(tasks.len()) {
+            let sender = sender.clone();
+            let next = &next;
+            scope.spawn(move || loop {
+                let i = next.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                let Some((file, config)) = tasks.get(i) else {
+                    break;
+                };
+                if sender.send((i, format_file(file, config, verify))).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+
+        // Results which came before the ones of earlier tasks
+        let mut pending = std::collections::BTreeMap::new();
+        let mut written = 0;
+        for (i, result) in receiver {
+            pending.insert(i, result);
+            while let Some(result) = pending.remove(&written) {
+                if let Err(err) = write(&tasks[written].0, result) {
+                    next.store(tasks.len(), std::sync::atomic::Ordering::Relaxed);
+                    return Err(err);
+                }
+                written += 1;
+            }
+        }
+        Ok(())
+    })
 }
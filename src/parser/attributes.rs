@@ -4,7 +4,7 @@
 
 // Port of djot.js/src/attributes.ts
 
-use crate::parser::Event;
+use crate::parser::Match;
 
 #[derive(Clone, Copy, PartialEq)]
 enum State {
@@ -33,7 +33,7 @@ pub struct AttributeParser<'a> {
     state: State,
     begin: Option<usize>,
     lastpos: Option<usize>,
-    matches: Vec<Event>,
+    matches: Vec<Match>,
 }
 
 impl<'a> AttributeParser<'a> {
@@ -48,7 +48,7 @@ impl<'a> AttributeParser<'a> {
     }
 
     fn add_event(&mut self, startpos: usize, endpos: usize, annot: &str) {
-        self.matches.push(Event {
+        self.matches.push(Match {
             startpos,
             endpos,
             annot: annot.to_string(),
@@ -320,7 +320,21 @@ impl<'a> AttributeParser<'a> {
         ("continue", endpos)
     }
 
-    pub fn get_matches(self) -> Vec<Event> {
+    pub fn get_matches(self) -> Vec<Match> {
         self.matches
     }
 }
+
+/// Whether `text` starts with attributes which are not closed yet, or are
+/// closed by its last non-blank character, so that the text which follows
+/// may still make it start with block attributes.
+pub(crate) fn attributes_may_continue(text: &str) -> bool {
+    if text.is_empty() {
+        return false;
+    }
+    match AttributeParser::new(text).feed(0, text.len() - 1) {
+        ("continue", _) => true,
+        ("done", end) => text[end + 1..].trim().is_empty(),
+        _ => false,
+    }
+}
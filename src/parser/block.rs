@@ -7,8 +7,9 @@
 use crate::parser::attributes::AttributeParser;
 use crate::parser::find;
 use crate::parser::inline::InlineParser;
-use crate::parser::Event;
+use crate::parser::Match;
 use regex::bytes::Regex;
+use std::collections::VecDeque;
 
 // All patterns compiled once, matching djot.js module-level constants.
 // Using find::pattern() to disable Unicode mode for byte-level matching.
@@ -118,6 +119,15 @@ fn is_eol_char(cp: u32) -> bool {
     cp == 10 || cp == 13
 }
 
+/// Whether `word` is a list marker, like `-` or `(iv)`.
+pub(crate) fn is_list_marker(word: &str) -> bool {
+    // The pattern includes the whitespace after the marker.
+    let line = format!("{} ", word);
+    PATT_LIST_MARKER
+        .find(line.as_bytes())
+        .is_some_and(|m| m.start() == 0 && m.end() == line.len())
+}
+
 fn get_list_styles(marker: &str) -> Vec<String> {
     let mb = marker.as_bytes();
     if marker == "+" || marker == "-" || marker == "*" || marker == ":" {
@@ -174,7 +184,7 @@ fn get_list_styles(marker: &str) -> Vec<String> {
     Vec::new()
 }
 
-struct EventParser<'a> {
+pub(super) struct EventParser<'a> {
     subject: &'a str,
     maxoffset: usize,
     len: usize,
@@ -183,14 +193,18 @@ struct EventParser<'a> {
     startline: usize,
     starteol: usize,
     endeol: usize,
-    matches: Vec<Event>,
+    /// Events not yet handed out by `next_event`.
+    matches: VecDeque<Match>,
     containers: Vec<Container<'a>>,
     last_matched_container: isize,
     finished_line: bool,
+    /// Set once every line has been processed and all containers are closed.
+    finished: bool,
 }
 
 impl<'a> EventParser<'a> {
-    fn new(subject: &'a str) -> Self {
+    /// Create a parser for `subject`, which must end with a newline.
+    pub(super) fn new(subject: &'a str) -> Self {
         let len = subject.len();
         let maxoffset = len - 1;
         EventParser {
@@ -202,15 +216,16 @@ impl<'a> EventParser<'a> {
             startline: 0,
             starteol: 0,
             endeol: 0,
-            matches: Vec::new(),
+            matches: VecDeque::new(),
             containers: Vec::new(),
             last_matched_container: -1,
             finished_line: false,
+            finished: false,
         }
     }
 
     fn add_match(&mut self, startpos: usize, endpos: usize, annot: &str) {
-        self.matches.push(Event {
+        self.matches.push_back(Match {
             startpos: startpos.min(self.maxoffset),
             endpos: endpos.min(self.maxoffset),
             annot: annot.to_string(),
@@ -592,7 +607,7 @@ impl<'a> EventParser<'a> {
             self.add_match(sp, sp, "+table");
             if self.parse_table_row(sp, sp + rawrow.len() - 1) {
             } else {
-                self.matches.pop();
+                self.matches.pop_back();
                 self.containers.pop();
                 return false;
             }
@@ -812,7 +827,7 @@ impl<'a> EventParser<'a> {
 
     // ---- Table parsing ----
 
-    fn parse_table_cell(&mut self, ep: usize) -> Option<(usize, usize, Vec<Event>)> {
+    fn parse_table_cell(&mut self, ep: usize) -> Option<(usize, usize, Vec<Match>)> {
         let mut inline_parser = InlineParser::new(self.subject);
         let sp = self.pos - 1;
         let mut cell_ep = sp;
@@ -904,9 +919,7 @@ impl<'a> EventParser<'a> {
                 self.add_match(cep, cep, "-cell");
             } else {
                 self.pos = startpos;
-                while self.matches.len() > orig_matches {
-                    self.matches.pop();
-                }
+                self.matches.truncate(orig_matches);
                 return false;
             }
         }
@@ -918,127 +931,142 @@ impl<'a> EventParser<'a> {
 
     // ---- Main loop ----
 
-    fn run(&mut self) -> Vec<Event> {
-        while self.pos < self.len {
-            self.indent = 0;
-            self.startline = self.pos;
-            self.finished_line = false;
-            self.get_eol();
+    /// Hand out the next event, parsing more lines as needed.
+    ///
+    /// Events are parsed a line at a time, so memory use is bounded by the
+    /// largest paragraph rather than the whole document. The last event is
+    /// held back until the end, as closing a container looks at its position.
+    pub(super) fn next_event(&mut self) -> Option<Match> {
+        while self.matches.len() < 2 && !self.finished {
+            if self.pos < self.len {
+                self.parse_line();
+            } else {
+                self.finish();
+            }
+        }
+        self.matches.pop_front()
+    }
 
-            self.last_matched_container = -1;
-            let mut idx = 0;
-            while idx < self.containers.len() {
-                self.skip_space();
-                let cont = self.containers[idx].name.clone();
-                let matches = match cont.as_str() {
-                    "block_quote" => self.continue_block_quote(idx),
-                    "heading" => self.continue_heading(idx),
-                    "footnote" => self.continue_footnote(idx),
-                    "reference_definition" => self.continue_reference_definition(idx),
-                    "list" => self.continue_list(idx),
-                    "list_item" => self.continue_list_item(idx),
-                    "table" => self.continue_table(idx),
-                    "attributes" => self.continue_attributes(idx),
-                    "fenced_div" => self.continue_fenced_div(idx),
-                    "code_block" => self.continue_code_block(idx),
-                    "para" | "caption" => self.find(&PATT_WHITESPACE).is_none(),
-                    _ => self.pos < self.starteol || self.starteol > self.startline,
-                };
-                if matches {
-                    self.last_matched_container = idx as isize;
-                } else {
-                    break;
-                }
-                idx += 1;
+    fn parse_line(&mut self) {
+        self.indent = 0;
+        self.startline = self.pos;
+        self.finished_line = false;
+        self.get_eol();
+
+        self.last_matched_container = -1;
+        let mut idx = 0;
+        while idx < self.containers.len() {
+            self.skip_space();
+            let cont = self.containers[idx].name.clone();
+            let matches = match cont.as_str() {
+                "block_quote" => self.continue_block_quote(idx),
+                "heading" => self.continue_heading(idx),
+                "footnote" => self.continue_footnote(idx),
+                "reference_definition" => self.continue_reference_definition(idx),
+                "list" => self.continue_list(idx),
+                "list_item" => self.continue_list_item(idx),
+                "table" => self.continue_table(idx),
+                "attributes" => self.continue_attributes(idx),
+                "fenced_div" => self.continue_fenced_div(idx),
+                "code_block" => self.continue_code_block(idx),
+                "para" | "caption" => self.find(&PATT_WHITESPACE).is_none(),
+                _ => self.pos < self.starteol || self.starteol > self.startline,
+            };
+            if matches {
+                self.last_matched_container = idx as isize;
+            } else {
+                break;
             }
+            idx += 1;
+        }
+
+        if self.finished_line {
+            while self.containers.len() as isize > self.last_matched_container + 1 {
+                self.close_tip();
+            }
+        }
 
-            if self.finished_line {
-                while self.containers.len() as isize > self.last_matched_container + 1 {
-                    self.close_tip();
+        if !self.finished_line {
+            self.skip_space();
+            let is_blank = self.pos == self.starteol;
+            let last_match = if self.last_matched_container >= 0 {
+                self.containers.get(self.last_matched_container as usize)
+            } else {
+                None
+            };
+            let mut last_match_content = last_match.map(|c| c.content);
+            let check_starts = !is_blank
+                && (last_match_content.is_none()
+                    || last_match_content == Some(ContentType::Block)
+                    || last_match_content == Some(ContentType::ListItem))
+                && self.find(&PATT_WORD).is_none();
+
+            let mut new_starts = false;
+            let mut check = check_starts;
+            while check {
+                check = false;
+                let spec_type = last_match_content.unwrap_or(ContentType::Block);
+                if self.try_spec(spec_type).is_some() {
+                    self.last_matched_container = self.containers.len() as isize - 1;
+                    new_starts = true;
+                    if !self.finished_line {
+                        self.skip_space();
+                        let tip = self.tip();
+                        let tip_content = tip.map(|t| t.content);
+                        last_match_content = tip_content;
+                        check = tip_content == Some(ContentType::Block)
+                            || tip_content == Some(ContentType::ListItem);
+                    }
                 }
             }
 
             if !self.finished_line {
                 self.skip_space();
                 let is_blank = self.pos == self.starteol;
-                let last_match = if self.last_matched_container >= 0 {
-                    self.containers.get(self.last_matched_container as usize)
-                } else {
-                    None
-                };
-                let mut last_match_content = last_match.map(|c| c.content);
-                let check_starts = !is_blank
-                    && (last_match_content.is_none()
-                        || last_match_content == Some(ContentType::Block)
-                        || last_match_content == Some(ContentType::ListItem))
-                    && self.find(&PATT_WORD).is_none();
-
-                let mut new_starts = false;
-                let mut check = check_starts;
-                while check {
-                    check = false;
-                    let spec_type = last_match_content.unwrap_or(ContentType::Block);
-                    if self.try_spec(spec_type).is_some() {
-                        self.last_matched_container = self.containers.len() as isize - 1;
-                        new_starts = true;
-                        if !self.finished_line {
-                            self.skip_space();
-                            let tip = self.tip();
-                            let tip_content = tip.map(|t| t.content);
-                            last_match_content = tip_content;
-                            check = tip_content == Some(ContentType::Block)
-                                || tip_content == Some(ContentType::ListItem);
-                        }
-                    }
+                let tip = self.tip();
+                let tip_content = tip.map(|c| c.content);
+                let is_lazy = !is_blank
+                    && !new_starts
+                    && self.last_matched_container < self.containers.len() as isize - 1
+                    && tip_content == Some(ContentType::Inline);
+
+                if !is_lazy {
+                    self.close_unmatched_containers();
                 }
 
-                if !self.finished_line {
-                    self.skip_space();
-                    let is_blank = self.pos == self.starteol;
-                    let tip = self.tip();
-                    let tip_content = tip.map(|c| c.content);
-                    let is_lazy = !is_blank
-                        && !new_starts
-                        && self.last_matched_container < self.containers.len() as isize - 1
-                        && tip_content == Some(ContentType::Inline);
-
-                    if !is_lazy {
-                        self.close_unmatched_containers();
-                    }
+                let tip = self.tip();
+                let tip_content = tip.map(|c| c.content);
 
-                    let tip = self.tip();
-                    let tip_content = tip.map(|c| c.content);
-
-                    if tip_content.is_none() || tip_content == Some(ContentType::Block) {
-                        if is_blank {
-                            if !new_starts {
-                                self.add_match(self.pos, self.endeol, "blankline");
-                            }
-                        } else {
-                            self.open_paragraph();
+                if tip_content.is_none() || tip_content == Some(ContentType::Block) {
+                    if is_blank {
+                        if !new_starts {
+                            self.add_match(self.pos, self.endeol, "blankline");
                         }
+                    } else {
+                        self.open_paragraph();
                     }
-                    if tip_content == Some(ContentType::Text) {
-                        let tip_indent = self.tip().map(|t| t.indent).unwrap_or(0);
-                        let mut startpos = self.pos;
-                        if self.indent > tip_indent {
-                            startpos -= self.indent - tip_indent;
-                        }
-                        self.add_match(startpos, self.endeol, "str");
-                    } else if tip_content == Some(ContentType::Inline) && !is_blank {
-                        if let Some(ref mut ip) = self.containers.last_mut().unwrap().inline_parser
-                        {
-                            ip.feed(self.pos, self.endeol);
-                        }
+                }
+                if tip_content == Some(ContentType::Text) {
+                    let tip_indent = self.tip().map(|t| t.indent).unwrap_or(0);
+                    let mut startpos = self.pos;
+                    if self.indent > tip_indent {
+                        startpos -= self.indent - tip_indent;
+                    }
+                    self.add_match(startpos, self.endeol, "str");
+                } else if tip_content == Some(ContentType::Inline) && !is_blank {
+                    if let Some(ref mut ip) = self.containers.last_mut().unwrap().inline_parser {
+                        ip.feed(self.pos, self.endeol);
                     }
                 }
             }
-            self.pos = self.endeol + 1;
         }
+        self.pos = self.endeol + 1;
+    }
 
+    fn finish(&mut self) {
         self.last_matched_container = -1;
         self.close_unmatched_containers();
-        self.matches.clone()
+        self.finished = true;
     }
 
     fn try_spec(&mut self, spec_type: ContentType) -> Option<()> {
@@ -1092,20 +1120,20 @@ impl<'a> EventParser<'a> {
                 "para" | "heading" | "caption" => {
                     if let Some(ip) = container.inline_parser {
                         let inline_matches = ip.get_matches();
-                        let mut last: Option<&Event> = None;
+                        let mut last: Option<&Match> = None;
                         for m in inline_matches {
                             if let Some(l) = last {
                                 if l.annot == "str"
                                     && m.annot == "str"
                                     && m.startpos == l.endpos + 1
                                 {
-                                    self.matches.last_mut().unwrap().endpos = m.endpos;
-                                    last = Some(&self.matches[self.matches.len() - 1]);
+                                    self.matches.back_mut().unwrap().endpos = m.endpos;
+                                    last = self.matches.back();
                                     continue;
                                 }
                             }
-                            self.matches.push(m);
-                            last = Some(&self.matches[self.matches.len() - 1]);
+                            self.matches.push_back(m);
+                            last = self.matches.back();
                         }
                     }
                     // NOTE: djot.js hardcodes this.pos - 1 for the close event position.
@@ -1113,7 +1141,7 @@ impl<'a> EventParser<'a> {
                     // equivalent because endpos is always < pos after inline parsing.
                     let last_ep = self
                         .matches
-                        .last()
+                        .back()
                         .map(|e| e.endpos + 1)
                         .unwrap_or(self.pos);
                     self.add_match(
@@ -1170,7 +1198,7 @@ impl<'a> EventParser<'a> {
                         );
                         if let Some(ap) = container.attribute_parser {
                             for m in ap.get_matches() {
-                                self.matches.push(m);
+                                self.matches.push_back(m);
                             }
                         }
                         self.add_match(self.pos, self.pos, "-block_attributes");
@@ -1205,7 +1233,7 @@ impl<'a> EventParser<'a> {
                 _ => {
                     let last_ep = self
                         .matches
-                        .last()
+                        .back()
                         .map(|e| e.endpos + 1)
                         .unwrap_or(self.pos);
                     self.add_match(
@@ -1220,8 +1248,3 @@ impl<'a> EventParser<'a> {
 }
 
 const C_SPACE: u32 = 32;
-
-pub fn parse(input: &str) -> Vec<Event> {
-    let mut parser = EventParser::new(input);
-    parser.run()
-}
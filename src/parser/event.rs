@@ -0,0 +1,500 @@
+// SPDX-FileCopyrightText: 2026 Chen Linxuan <me@black-desk.cn>
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Typed form of the djot.js event annotations.
+
+/// A container opened by `+name` and closed by `-name` events.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Container {
+    // Blocks
+    Para,
+    Heading,
+    BlockQuote,
+    /// A list, with the styles its marker may have, like `1.` or `i)`.
+    ///
+    /// A marker like `i.` is ambiguous until a later item is seen, so
+    /// several styles may remain. Close events carry no styles.
+    List(Vec<ListStyle>),
+    /// A list item, with the styles its marker may have.
+    ListItem(Vec<ListStyle>),
+    Table,
+    Row,
+    Cell,
+    Caption,
+    CodeBlock,
+    Div,
+    Footnote,
+    ReferenceDefinition,
+    BlockAttributes,
+    // Inlines
+    Strong,
+    Emph,
+    Subscript,
+    Superscript,
+    Insert,
+    Delete,
+    Mark,
+    Span,
+    SingleQuoted,
+    DoubleQuoted,
+    LinkText,
+    ImageText,
+    Destination,
+    Reference,
+    Verbatim,
+    InlineMath,
+    DisplayMath,
+    Url,
+    Email,
+    Attributes,
+}
+
+/// The style of a list, given by the markers of its items.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListStyle {
+    /// A bullet list, with its bullet `-`, `*` or `+`.
+    Bullet(char),
+    /// A task list, with the bullet of its items.
+    Task(char),
+    /// A definition list, marked with `:`.
+    Definition,
+    /// An ordered list, like `1.`, `a)` or `(I)`.
+    Ordered(Enumerator, Delimiter),
+}
+
+/// How the items of an ordered list are numbered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Enumerator {
+    Decimal,
+    AlphaLower,
+    AlphaUpper,
+    RomanLower,
+    RomanUpper,
+}
+
+/// The delimiter of ordered list markers: `1.`, `1)` or `(1)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delimiter {
+    Period,
+    Paren,
+    Parens,
+}
+
+/// The kind of a parsing event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EventKind {
+    Open(Container),
+    Close(Container),
+    Str,
+    SoftBreak,
+    HardBreak,
+    NonBreakingSpace,
+    Blankline,
+    ThematicBreak,
+    Escape,
+    Symb,
+    FootnoteReference,
+    OpenMarker,
+    ImageMarker,
+    CodeLanguage,
+    RawFormat,
+    NoteLabel,
+    ReferenceKey,
+    ReferenceValue,
+    CheckboxChecked,
+    CheckboxUnchecked,
+    LeftSingleQuote,
+    RightSingleQuote,
+    LeftDoubleQuote,
+    RightDoubleQuote,
+    EnDash,
+    EmDash,
+    Ellipses,
+    SeparatorDefault,
+    SeparatorLeft,
+    SeparatorRight,
+    SeparatorCenter,
+    AttrClassMarker,
+    AttrIdMarker,
+    AttrEqualMarker,
+    AttrQuoteMarker,
+    AttrSpace,
+    Class,
+    Id,
+    Key,
+    Value,
+    Comment,
+}
+
+/// An annotation no event kind matches.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownAnnotation(pub String);
+
+impl std::fmt::Display for UnknownAnnotation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unknown event annotation {:?}", self.0)
+    }
+}
+
+impl std::error::Error for UnknownAnnotation {}
+
+impl Enumerator {
+    /// Whether markers of this kind may also read as ones of `other`, like
+    /// `i.` which is both roman and alphabetic.
+    pub fn overlaps(self, other: Enumerator) -> bool {
+        use Enumerator::*;
+        matches!(
+            (self, other),
+            (Decimal, Decimal)
+                | (AlphaLower | RomanLower, AlphaLower | RomanLower)
+                | (AlphaUpper | RomanUpper, AlphaUpper | RomanUpper)
+        )
+    }
+
+    /// The number `enumerator`, like `iv` or `b`, stands for, if it is one of
+    /// this kind.
+    fn value(self, enumerator: &str) -> Option<u64> {
+        match self {
+            Enumerator::Decimal => enumerator.parse().ok(),
+            Enumerator::AlphaLower | Enumerator::AlphaUpper => {
+                let mut chars = enumerator.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) if c.is_ascii_alphabetic() => {
+                        Some((c.to_ascii_lowercase() as u8 - b'a') as u64 + 1)
+                    }
+                    _ => None,
+                }
+            }
+            Enumerator::RomanLower | Enumerator::RomanUpper => {
+                roman_value(&enumerator.to_uppercase())
+            }
+        }
+    }
+
+    /// The enumerator of the first item, as in annotations.
+    fn first(self) -> char {
+        match self {
+            Enumerator::Decimal => '1',
+            Enumerator::AlphaLower => 'a',
+            Enumerator::AlphaUpper => 'A',
+            Enumerator::RomanLower => 'i',
+            Enumerator::RomanUpper => 'I',
+        }
+    }
+}
+
+impl Delimiter {
+    /// The marker with `enumerator` between this delimiter, like `(3)`.
+    pub fn mark(self, enumerator: &str) -> String {
+        match self {
+            Delimiter::Period => format!("{}.", enumerator),
+            Delimiter::Paren => format!("{})", enumerator),
+            Delimiter::Parens => format!("({})", enumerator),
+        }
+    }
+}
+
+/// The value of the upper case roman numeral `numeral`, if valid, allowing
+/// thousands written as repeated `M` past the 3999 of standard numerals.
+fn roman_value(numeral: &str) -> Option<u64> {
+    let rest = numeral.trim_start_matches('M');
+    let thousands = (numeral.len() - rest.len()) as u64;
+    if rest.is_empty() {
+        return (thousands > 0).then_some(thousands * 1000);
+    }
+    roman::from(rest).map(|n| thousands * 1000 + n as u64)
+}
+
+impl ListStyle {
+    /// The number of the item with `marker`, like `(iv)` or `b.`, in a list
+    /// of this style. Bullets and invalid markers count as 1.
+    pub fn number(self, marker: &str) -> u64 {
+        let enumerator = marker
+            .trim()
+            .trim_start_matches('(')
+            .trim_end_matches(['.', ')']);
+        match self {
+            ListStyle::Ordered(kind, _) => kind.value(enumerator),
+            _ => None,
+        }
+        .unwrap_or(1)
+    }
+
+    fn parse(style: &str) -> Option<Self> {
+        let mut chars = style.chars();
+        Some(match (chars.next()?, chars.as_str()) {
+            (bullet @ ('-' | '*' | '+'), "") => ListStyle::Bullet(bullet),
+            (bullet @ ('-' | '*' | '+'), "X") => ListStyle::Task(bullet),
+            (':', "") => ListStyle::Definition,
+            _ => {
+                let (enumerator, delimiter) = if let Some(inner) = style.strip_prefix('(') {
+                    (inner.strip_suffix(')')?, Delimiter::Parens)
+                } else if let Some(enumerator) = style.strip_suffix(')') {
+                    (enumerator, Delimiter::Paren)
+                } else {
+                    (style.strip_suffix('.')?, Delimiter::Period)
+                };
+                let enumerator = match enumerator {
+                    "1" => Enumerator::Decimal,
+                    "a" => Enumerator::AlphaLower,
+                    "A" => Enumerator::AlphaUpper,
+                    "i" => Enumerator::RomanLower,
+                    "I" => Enumerator::RomanUpper,
+                    _ => return None,
+                };
+                ListStyle::Ordered(enumerator, delimiter)
+            }
+        })
+    }
+}
+
+impl std::fmt::Display for ListStyle {
+    /// Formats as in annotations, e.g. `-X` or `(i)`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ListStyle::Bullet(bullet) => write!(f, "{}", bullet),
+            ListStyle::Task(bullet) => write!(f, "{}X", bullet),
+            ListStyle::Definition => f.write_str(":"),
+            ListStyle::Ordered(enumerator, delimiter) => {
+                f.write_str(&delimiter.mark(&enumerator.first().to_string()))
+            }
+        }
+    }
+}
+
+impl Container {
+    /// The name used in annotations, e.g. `list_item`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Container::Para => "para",
+            Container::Heading => "heading",
+            Container::BlockQuote => "block_quote",
+            Container::List(_) => "list",
+            Container::ListItem(_) => "list_item",
+            Container::Table => "table",
+            Container::Row => "row",
+            Container::Cell => "cell",
+            Container::Caption => "caption",
+            Container::CodeBlock => "code_block",
+            Container::Div => "div",
+            Container::Footnote => "footnote",
+            Container::ReferenceDefinition => "reference_definition",
+            Container::BlockAttributes => "block_attributes",
+            Container::Strong => "strong",
+            Container::Emph => "emph",
+            Container::Subscript => "subscript",
+            Container::Superscript => "superscript",
+            Container::Insert => "insert",
+            Container::Delete => "delete",
+            Container::Mark => "mark",
+            Container::Span => "span",
+            Container::SingleQuoted => "single_quoted",
+            Container::DoubleQuoted => "double_quoted",
+            Container::LinkText => "linktext",
+            Container::ImageText => "imagetext",
+            Container::Destination => "destination",
+            Container::Reference => "reference",
+            Container::Verbatim => "verbatim",
+            Container::InlineMath => "inline_math",
+            Container::DisplayMath => "display_math",
+            Container::Url => "url",
+            Container::Email => "email",
+            Container::Attributes => "attributes",
+        }
+    }
+
+    /// Whether this is a block-level container.
+    pub fn is_block(&self) -> bool {
+        matches!(
+            self,
+            Container::Para
+                | Container::Heading
+                | Container::BlockQuote
+                | Container::List(_)
+                | Container::ListItem(_)
+                | Container::Table
+                | Container::Caption
+                | Container::CodeBlock
+                | Container::Div
+                | Container::Footnote
+                | Container::ReferenceDefinition
+                | Container::BlockAttributes
+        )
+    }
+
+    fn parse(annot: &str) -> Option<Self> {
+        let mut parts = annot.split('|');
+        let name = parts.next()?;
+        let styles = || parts.map(ListStyle::parse).collect::<Option<_>>();
+        Some(match name {
+            "para" => Container::Para,
+            "heading" => Container::Heading,
+            "block_quote" => Container::BlockQuote,
+            "list" => Container::List(styles()?),
+            "list_item" => Container::ListItem(styles()?),
+            "table" => Container::Table,
+            "row" => Container::Row,
+            "cell" => Container::Cell,
+            "caption" => Container::Caption,
+            "code_block" => Container::CodeBlock,
+            "div" => Container::Div,
+            "footnote" => Container::Footnote,
+            "reference_definition" => Container::ReferenceDefinition,
+            "block_attributes" => Container::BlockAttributes,
+            "strong" => Container::Strong,
+            "emph" => Container::Emph,
+            "subscript" => Container::Subscript,
+            "superscript" => Container::Superscript,
+            "insert" => Container::Insert,
+            "delete" => Container::Delete,
+            "mark" => Container::Mark,
+            "span" => Container::Span,
+            "single_quoted" => Container::SingleQuoted,
+            "double_quoted" => Container::DoubleQuoted,
+            "linktext" => Container::LinkText,
+            "imagetext" => Container::ImageText,
+            "destination" => Container::Destination,
+            "reference" => Container::Reference,
+            "verbatim" => Container::Verbatim,
+            "inline_math" => Container::InlineMath,
+            "display_math" => Container::DisplayMath,
+            "url" => Container::Url,
+            "email" => Container::Email,
+            "attributes" => Container::Attributes,
+            _ => return None,
+        })
+    }
+}
+
+impl std::fmt::Display for Container {
+    /// Formats as in annotations, e.g. `list|1.|i.`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())?;
+        if let Container::List(styles) | Container::ListItem(styles) = self {
+            for style in styles {
+                write!(f, "|{}", style)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl EventKind {
+    /// The annotation of leaf events, `None` for containers.
+    pub fn leaf_name(&self) -> Option<&'static str> {
+        Some(match self {
+            EventKind::Open(_) | EventKind::Close(_) => return None,
+            EventKind::Str => "str",
+            EventKind::SoftBreak => "soft_break",
+            EventKind::HardBreak => "hard_break",
+            EventKind::NonBreakingSpace => "non_breaking_space",
+            EventKind::Blankline => "blankline",
+            EventKind::ThematicBreak => "thematic_break",
+            EventKind::Escape => "escape",
+            EventKind::Symb => "symb",
+            EventKind::FootnoteReference => "footnote_reference",
+            EventKind::OpenMarker => "open_marker",
+            EventKind::ImageMarker => "image_marker",
+            EventKind::CodeLanguage => "code_language",
+            EventKind::RawFormat => "raw_format",
+            EventKind::NoteLabel => "note_label",
+            EventKind::ReferenceKey => "reference_key",
+            EventKind::ReferenceValue => "reference_value",
+            EventKind::CheckboxChecked => "checkbox_checked",
+            EventKind::CheckboxUnchecked => "checkbox_unchecked",
+            EventKind::LeftSingleQuote => "left_single_quote",
+            EventKind::RightSingleQuote => "right_single_quote",
+            EventKind::LeftDoubleQuote => "left_double_quote",
+            EventKind::RightDoubleQuote => "right_double_quote",
+            EventKind::EnDash => "en_dash",
+            EventKind::EmDash => "em_dash",
+            EventKind::Ellipses => "ellipses",
+            EventKind::SeparatorDefault => "separator_default",
+            EventKind::SeparatorLeft => "separator_left",
+            EventKind::SeparatorRight => "separator_right",
+            EventKind::SeparatorCenter => "separator_center",
+            EventKind::AttrClassMarker => "attr_class_marker",
+            EventKind::AttrIdMarker => "attr_id_marker",
+            EventKind::AttrEqualMarker => "attr_equal_marker",
+            EventKind::AttrQuoteMarker => "attr_quote_marker",
+            EventKind::AttrSpace => "attr_space",
+            EventKind::Class => "class",
+            EventKind::Id => "id",
+            EventKind::Key => "key",
+            EventKind::Value => "value",
+            EventKind::Comment => "comment",
+        })
+    }
+}
+
+impl std::str::FromStr for EventKind {
+    type Err = UnknownAnnotation;
+
+    fn from_str(annot: &str) -> Result<Self, Self::Err> {
+        let unknown = || UnknownAnnotation(annot.to_string());
+        if let Some(rest) = annot.strip_prefix('+') {
+            return Container::parse(rest)
+                .map(EventKind::Open)
+                .ok_or_else(unknown);
+        }
+        if let Some(rest) = annot.strip_prefix('-') {
+            return Container::parse(rest)
+                .map(EventKind::Close)
+                .ok_or_else(unknown);
+        }
+        Ok(match annot {
+            "str" => EventKind::Str,
+            "soft_break" => EventKind::SoftBreak,
+            "hard_break" => EventKind::HardBreak,
+            "non_breaking_space" => EventKind::NonBreakingSpace,
+            "blankline" => EventKind::Blankline,
+            "thematic_break" => EventKind::ThematicBreak,
+            "escape" => EventKind::Escape,
+            "symb" => EventKind::Symb,
+            "footnote_reference" => EventKind::FootnoteReference,
+            "open_marker" => EventKind::OpenMarker,
+            "image_marker" => EventKind::ImageMarker,
+            "code_language" => EventKind::CodeLanguage,
+            "raw_format" => EventKind::RawFormat,
+            "note_label" => EventKind::NoteLabel,
+            "reference_key" => EventKind::ReferenceKey,
+            "reference_value" => EventKind::ReferenceValue,
+            "checkbox_checked" => EventKind::CheckboxChecked,
+            "checkbox_unchecked" => EventKind::CheckboxUnchecked,
+            "left_single_quote" => EventKind::LeftSingleQuote,
+            "right_single_quote" => EventKind::RightSingleQuote,
+            "left_double_quote" => EventKind::LeftDoubleQuote,
+            "right_double_quote" => EventKind::RightDoubleQuote,
+            "en_dash" => EventKind::EnDash,
+            "em_dash" => EventKind::EmDash,
+            "ellipses" => EventKind::Ellipses,
+            "separator_default" => EventKind::SeparatorDefault,
+            "separator_left" => EventKind::SeparatorLeft,
+            "separator_right" => EventKind::SeparatorRight,
+            "separator_center" => EventKind::SeparatorCenter,
+            "attr_class_marker" => EventKind::AttrClassMarker,
+            "attr_id_marker" => EventKind::AttrIdMarker,
+            "attr_equal_marker" => EventKind::AttrEqualMarker,
+            "attr_quote_marker" => EventKind::AttrQuoteMarker,
+            "attr_space" => EventKind::AttrSpace,
+            "class" => EventKind::Class,
+            "id" => EventKind::Id,
+            "key" => EventKind::Key,
+            "value" => EventKind::Value,
+            "comment" => EventKind::Comment,
+            _ => return Err(unknown()),
+        })
+    }
+}
+
+impl std::fmt::Display for EventKind {
+    /// Formats as the djot.js annotation, e.g. `+list|1.` or `str`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EventKind::Open(container) => write!(f, "+{}", container),
+            EventKind::Close(container) => write!(f, "-{}", container),
+            leaf => f.write_str(leaf.leaf_name().unwrap_or_default()),
+        }
+    }
+}
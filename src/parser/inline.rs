@@ -6,7 +6,7 @@
 
 use crate::parser::attributes::AttributeParser;
 use crate::parser::find;
-use crate::parser::Event;
+use crate::parser::Match;
 use regex::bytes::Regex;
 
 // All patterns compiled once. Unicode disabled for byte-level matching.
@@ -73,6 +73,13 @@ fn cp(subject: &str, pos: usize) -> u32 {
     subject.as_bytes().get(pos).copied().unwrap_or(0) as u32
 }
 
+/// Whether `c` may have a meaning in inline content, so that a backslash
+/// escaping it may not be redundant.
+pub fn is_special(c: char) -> bool {
+    let mut buf = [0; 4];
+    RE_SPECIAL.is_match(c.encode_utf8(&mut buf).as_bytes())
+}
+
 fn find_special(subject: &str, startpos: usize, endpos: usize) -> Option<usize> {
     let subj = &subject.as_bytes()[startpos..];
     if let Some(mat) = RE_SPECIAL.find_iter(subj).next() {
@@ -90,7 +97,7 @@ fn has_brace(subject: &str, pos: usize) -> bool {
 
 pub struct InlineParser<'a> {
     subject: &'a str,
-    matches: Vec<Event>,
+    matches: Vec<Match>,
     openers: OpenerMap,
     pub verbatim: usize,
     verbatim_type: String,
@@ -127,7 +134,7 @@ impl<'a> InlineParser<'a> {
     }
 
     fn add_match(&mut self, startpos: usize, endpos: usize, annot: &str) {
-        self.matches.push(Event {
+        self.matches.push(Match {
             startpos,
             endpos,
             annot: annot.to_string(),
@@ -136,7 +143,7 @@ impl<'a> InlineParser<'a> {
 
     fn add_match_at(&mut self, idx: usize, startpos: usize, endpos: usize, annot: &str) {
         if idx < self.matches.len() {
-            self.matches[idx] = Event {
+            self.matches[idx] = Match {
                 startpos,
                 endpos,
                 annot: annot.to_string(),
@@ -174,7 +181,7 @@ impl<'a> InlineParser<'a> {
         self.allow_attributes = true;
     }
 
-    pub fn get_matches(mut self) -> Vec<Event> {
+    pub fn get_matches(mut self) -> Vec<Match> {
         let subject = self.subject;
         if self.attribute_parser.is_some() {
             self.reparse_attributes();
@@ -201,7 +208,7 @@ impl<'a> InlineParser<'a> {
         // add -verbatim if needed (unclosed verbatim)
         if !self.matches.is_empty() && self.verbatim > 0 {
             let last = self.matches.last().unwrap();
-            self.matches.push(Event {
+            self.matches.push(Match {
                 startpos: last.endpos,
                 endpos: last.endpos,
                 annot: format!("-{}", self.verbatim_type),
@@ -4,25 +4,106 @@
 
 mod attributes;
 mod block;
+mod event;
 mod find;
 mod inline;
 
-/// A parsing event, identical in structure to djot.js's Event.
+pub use event::{Container, Delimiter, Enumerator, EventKind, ListStyle, UnknownAnnotation};
+pub use inline::is_special;
+
+pub(crate) use attributes::attributes_may_continue;
+pub(crate) use block::is_list_marker;
+
+/// A parsing event, like djot.js's Event with a typed annotation.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Event {
     pub startpos: usize,
     pub endpos: usize,
-    pub annot: String,
+    pub kind: EventKind,
+}
+
+impl Event {
+    /// The annotation of djot.js, e.g. `+list|1.` or `str`.
+    pub fn annot(&self) -> String {
+        self.kind.to_string()
+    }
+}
+
+/// An event as the parser builds it, with the annotation of djot.js.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Match {
+    startpos: usize,
+    endpos: usize,
+    annot: String,
+}
+
+impl From<Match> for Event {
+    fn from(m: Match) -> Self {
+        let kind = m
+            .annot
+            .parse()
+            .unwrap_or_else(|err| unreachable!("The parser produced an {}", err));
+        Event {
+            startpos: m.startpos,
+            endpos: m.endpos,
+            kind,
+        }
+    }
+}
+
+/// A Djot document ready to be parsed.
+///
+/// The parser expects the document to end with a newline. The input is
+/// borrowed as is when it does, and only copied to append one when it does
+/// not. Event positions are byte offsets into [`Source::as_str`], which only
+/// differs from the input by that final newline.
+pub struct Source<'a> {
+    text: std::borrow::Cow<'a, str>,
+}
+
+impl<'a> Source<'a> {
+    pub fn new(input: &'a str) -> Self {
+        let text = if input.ends_with('\n') {
+            std::borrow::Cow::Borrowed(input)
+        } else {
+            std::borrow::Cow::Owned(format!("{}\n", input))
+        };
+        Self { text }
+    }
+
+    /// The text events refer to.
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    /// Parse the document lazily into an event stream compatible with
+    /// djot.js.
+    pub fn events(&self) -> Events<'_> {
+        Events {
+            parser: block::EventParser::new(&self.text),
+        }
+    }
+}
+
+/// A pull parser producing the events of a [`Source`] in order.
+///
+/// Lines are parsed as events are requested, so memory use stays bounded by
+/// the largest leaf block instead of growing with the document.
+pub struct Events<'a> {
+    parser: block::EventParser<'a>,
+}
+
+impl Iterator for Events<'_> {
+    type Item = Event;
+
+    fn next(&mut self) -> Option<Event> {
+        self.parser.next_event().map(Event::from)
+    }
 }
 
 /// Parse a Djot document into an event stream compatible with djot.js.
 ///
 /// Internally uses byte offsets for O(1) character access.
 pub fn parse_events(input: &str) -> Vec<Event> {
-    let text = if input.ends_with('\n') {
-        input.to_string()
-    } else {
-        format!("{}\n", input)
-    };
-    block::parse(&text)
+    Source::new(input).events().collect()
 }
@@ -0,0 +1,511 @@
+// SPDX-FileCopyrightText: 2026 Chen Linxuan <me@black-desk.cn>
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Self-verification of the formatter output.
+//!
+//! The formatter must never change what a document means. [`verify`] checks
+//! this after the fact: it re-parses the formatted output and compares a
+//! normalized view of its event stream with the one of the original, then
+//! formats the output once more to check that formatting is idempotent.
+//!
+//! The normalized view ([`semantic_events`]) drops everything the formatter
+//! is allowed to change: source positions, how text is broken into lines, the
+//! amount of whitespace outside code and math, redundant escapes, blank lines
+//! (except for how they make a list loose), whether smart quotes are written
+//! with braces, the padding of inline verbatim and, unless configured to be
+//! kept, the delimiters, bullets and spacing of lists. Smart punctuation
+//! written as the characters it stands for is compared as such.
+
+use crate::fmt::{self, Bullet, FmtConfig, ListDelimiter, ListSpacing, SmartPunctuation};
+use crate::parser::{self, Container, Delimiter, Event, EventKind, ListStyle};
+
+/// An element of the normalized event stream of a document.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SemanticEvent {
+    /// Start of a container, with the details that affect its meaning. Items
+    /// of lists have no styles, their lists have them.
+    Open(Container, Details),
+    /// End of a container.
+    Close(Container),
+    /// Text content, with whitespace collapsed unless it is in a code block,
+    /// verbatim (including raw inlines) or math.
+    Text(String),
+    /// Any other leaf event, with its content if it matters, e.g. the name of
+    /// a symbol.
+    Leaf(EventKind, Option<String>),
+}
+
+/// What a container means beyond its events.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Details {
+    None,
+    /// The level of a heading.
+    Heading(usize),
+    /// The number of the first item of a list, and whether the list is loose
+    /// unless that is ignored.
+    List {
+        start: u64,
+        loose: Option<bool>,
+    },
+}
+
+impl std::fmt::Display for Details {
+    /// Formats as parts of a tag, e.g. `|2` or `|start=5|loose`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Details::None => Ok(()),
+            Details::Heading(level) => write!(f, "|{}", level),
+            Details::List { start, loose } => {
+                write!(f, "|start={}", start)?;
+                match loose {
+                    Some(true) => f.write_str("|loose"),
+                    Some(false) => f.write_str("|tight"),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for SemanticEvent {
+    /// Formats like annotations, e.g. `+heading|2`, `+list|1.|start=5|loose`
+    /// or `symb|smile`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SemanticEvent::Open(container, details) => write!(f, "+{}{}", container, details),
+            SemanticEvent::Close(container) => write!(f, "-{}", container),
+            SemanticEvent::Text(text) => write!(f, "{:?}", text),
+            SemanticEvent::Leaf(kind, None) => write!(f, "{}", kind),
+            SemanticEvent::Leaf(kind, Some(content)) => write!(f, "{}|{}", kind, content),
+        }
+    }
+}
+
+/// Why the formatter output failed verification.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum VerifyError {
+    /// The output does not mean the same as the input.
+    SemanticChange {
+        /// Index of the first differing event in the normalized streams.
+        index: usize,
+        /// The event of the input at `index`, if any.
+        expected: Option<Box<SemanticEvent>>,
+        /// The event of the output at `index`, if any.
+        actual: Option<Box<SemanticEvent>>,
+    },
+    /// Formatting the output again changes it.
+    NotIdempotent {
+        /// 1-based number of the first line that changes.
+        line: usize,
+    },
+}
+
+impl std::fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let show = |event: &Option<Box<SemanticEvent>>| match event {
+            Some(event) => event.to_string(),
+            None => "end of document".to_string(),
+        };
+        match self {
+            VerifyError::SemanticChange {
+                index,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "formatting changes the document: event #{} is {} instead of {}",
+                index,
+                show(actual),
+                show(expected)
+            ),
+            VerifyError::NotIdempotent { line } => write!(
+                f,
+                "formatting is not idempotent: formatting again changes line {}",
+                line
+            ),
+        }
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+/// Check that `output`, the result of formatting `input` with `config`,
+/// means the same as `input` and is itself formatted.
+pub fn verify(input: &str, output: &str, config: &FmtConfig) -> Result<(), VerifyError> {
+    let normalize = |events: Vec<SemanticEvent>| {
+        let mut events = if config.smart_punctuation == SmartPunctuation::Unicode {
+            smart_punctuation_as_text(events)
+        } else {
+            events
+        };
+        normalize_text(&mut events);
+        events
+    };
+    let mut expected = normalize(parse_events(input));
+    let mut actual = normalize(parse_events(output));
+    for event in expected.iter_mut().chain(actual.iter_mut()) {
+        if let SemanticEvent::Open(Container::List(styles), details) = event {
+            ignore_list_changes(styles, details, config);
+        }
+    }
+    if expected != actual {
+        let index = expected
+            .iter()
+            .zip(&actual)
+            .position(|(e, a)| e != a)
+            .unwrap_or(expected.len().min(actual.len()));
+        return Err(VerifyError::SemanticChange {
+            index,
+            expected: expected.get(index).cloned().map(Box::new),
+            actual: actual.get(index).cloned().map(Box::new),
+        });
+    }
+
+    let again = fmt::format(output, config);
+    if again != output {
+        let line = output
+            .lines()
+            .zip(again.lines())
+            .position(|(o, a)| o != a)
+            .unwrap_or(output.lines().count().min(again.lines().count()));
+        return Err(VerifyError::NotIdempotent { line: line + 1 });
+    }
+
+    Ok(())
+}
+
+/// Replace smart punctuation in `events` with the characters it stands for,
+/// joined with the text around them.
+fn smart_punctuation_as_text(events: Vec<SemanticEvent>) -> Vec<SemanticEvent> {
+    let mut out: Vec<SemanticEvent> = Vec::with_capacity(events.len());
+    for event in events {
+        let character = match &event {
+            SemanticEvent::Leaf(kind, _) => match kind {
+                EventKind::LeftSingleQuote => Some('\u{2018}'),
+                EventKind::RightSingleQuote => Some('\u{2019}'),
+                EventKind::LeftDoubleQuote => Some('\u{201c}'),
+                EventKind::RightDoubleQuote => Some('\u{201d}'),
+                EventKind::EnDash => Some('\u{2013}'),
+                EventKind::EmDash => Some('\u{2014}'),
+                EventKind::Ellipses => Some('\u{2026}'),
+                _ => None,
+            },
+            _ => None,
+        };
+        let event = match character {
+            Some(character) => SemanticEvent::Text(character.to_string()),
+            None => event,
+        };
+        match (out.last_mut(), event) {
+            (Some(SemanticEvent::Text(last)), SemanticEvent::Text(text)) => last.push_str(&text),
+            (_, event) => out.push(event),
+        }
+    }
+    out
+}
+
+/// Per-list state used to find out whether a list is loose.
+struct ListState {
+    /// Index of the list's `Open` event, completed when the list closes.
+    open: usize,
+    /// A blank line was seen and no block has started since.
+    pending_blank: bool,
+    loose: bool,
+}
+
+/// Compute the normalized event stream of a Djot document.
+pub fn semantic_events(input: &str) -> Vec<SemanticEvent> {
+    let mut events = parse_events(input);
+    normalize_text(&mut events);
+    events
+}
+
+/// Compute the event stream of a Djot document, with its text as in the
+/// source.
+fn parse_events(input: &str) -> Vec<SemanticEvent> {
+    let source = parser::Source::new(input);
+    let text = source.as_str();
+
+    let src = |event: &Event| -> String {
+        let bytes = text.as_bytes();
+        let end = (event.endpos + 1).min(bytes.len());
+        let start = event.startpos.min(end);
+        String::from_utf8_lossy(&bytes[start..end]).into_owned()
+    };
+
+    let mut out: Vec<SemanticEvent> = Vec::new();
+    let mut lists: Vec<ListState> = Vec::new();
+    // Text is kept as it is inside code blocks.
+    let mut verbatim_text = false;
+    // Whitespace is meaningless inside link destinations.
+    let mut in_destination = false;
+    // Reference definition values can span several lines.
+    let mut reference_value = String::new();
+
+    let push_text = |out: &mut Vec<SemanticEvent>, text: &str| {
+        if let Some(SemanticEvent::Text(last)) = out.last_mut() {
+            last.push_str(text);
+        } else {
+            out.push(SemanticEvent::Text(text.to_string()));
+        }
+    };
+
+    let mut events = source.events().peekable();
+    while let Some(event) = events.next() {
+        let event = &event;
+        match &event.kind {
+            EventKind::Open(container) => {
+                // A block starting after a blank line inside a list makes it
+                // loose.
+                if container.is_block() {
+                    if let Some(list) = lists.last_mut() {
+                        list.loose |= list.pending_blank;
+                        list.pending_blank = false;
+                    }
+                }
+                let details = match container {
+                    Container::Heading => {
+                        let level = src(event).chars().take_while(|c| *c == '#').count();
+                        Details::Heading(level)
+                    }
+                    Container::List(styles) => {
+                        let start = styles
+                            .first()
+                            .zip(events.peek())
+                            .map_or(1, |(style, item)| style.number(&src(item)));
+                        lists.push(ListState {
+                            open: out.len(),
+                            pending_blank: false,
+                            loose: false,
+                        });
+                        Details::List { start, loose: None }
+                    }
+                    Container::CodeBlock => {
+                        verbatim_text = true;
+                        Details::None
+                    }
+                    Container::Destination => {
+                        in_destination = true;
+                        Details::None
+                    }
+                    _ => Details::None,
+                };
+                out.push(match container {
+                    Container::ListItem(_) => {
+                        SemanticEvent::Open(Container::ListItem(Vec::new()), details)
+                    }
+                    Container::SingleQuoted => {
+                        SemanticEvent::Leaf(EventKind::LeftSingleQuote, None)
+                    }
+                    Container::DoubleQuoted => {
+                        SemanticEvent::Leaf(EventKind::LeftDoubleQuote, None)
+                    }
+                    container => SemanticEvent::Open(container.clone(), details),
+                });
+            }
+            EventKind::Close(container) => {
+                match container {
+                    Container::List(_) => {
+                        if let Some(list) = lists.pop() {
+                            if let SemanticEvent::Open(_, Details::List { loose, .. }) =
+                                &mut out[list.open]
+                            {
+                                *loose = Some(list.loose);
+                            }
+                            // A blank line after a nested list separates blocks
+                            // of the enclosing list.
+                            if let Some(parent) = lists.last_mut() {
+                                parent.pending_blank |= list.pending_blank;
+                            }
+                        }
+                    }
+                    Container::CodeBlock => verbatim_text = false,
+                    Container::Destination => in_destination = false,
+                    // A single space separating the content from backticks in
+                    // the delimiters is not part of the content.
+                    Container::Verbatim => {
+                        if let Some(SemanticEvent::Text(text)) = out.last_mut() {
+                            if text.ends_with("` ") {
+                                text.pop();
+                            }
+                            if text.starts_with(" `") {
+                                text.remove(0);
+                            }
+                        }
+                    }
+                    Container::ReferenceDefinition if !reference_value.is_empty() => {
+                        out.push(SemanticEvent::Leaf(
+                            EventKind::ReferenceValue,
+                            Some(std::mem::take(&mut reference_value)),
+                        ));
+                    }
+                    _ => {}
+                }
+                out.push(match container {
+                    Container::SingleQuoted => {
+                        SemanticEvent::Leaf(EventKind::RightSingleQuote, None)
+                    }
+                    Container::DoubleQuoted => {
+                        SemanticEvent::Leaf(EventKind::RightDoubleQuote, None)
+                    }
+                    container => SemanticEvent::Close(container.clone()),
+                });
+            }
+            EventKind::Str if verbatim_text => push_text(&mut out, &src(event)),
+            EventKind::Str if in_destination => {
+                let url: String = src(event).chars().filter(|c| !c.is_whitespace()).collect();
+                push_text(&mut out, &url);
+            }
+            EventKind::Str => push_text(&mut out, &src(event)),
+            EventKind::SoftBreak => push_text(&mut out, " "),
+            EventKind::Blankline => {
+                if let Some(list) = lists.last_mut() {
+                    list.pending_blank = true;
+                }
+            }
+            // Escapes only matter through the events that follow them.
+            EventKind::Escape
+            | EventKind::OpenMarker
+            | EventKind::AttrSpace
+            | EventKind::AttrQuoteMarker => {}
+            EventKind::ReferenceValue => reference_value.push_str(src(event).trim()),
+            EventKind::Comment => {
+                let comment = src(event);
+                let comment = comment.trim_start_matches('%').trim_end_matches('%');
+                let words: Vec<&str> = comment.split_whitespace().collect();
+                out.push(SemanticEvent::Leaf(
+                    EventKind::Comment,
+                    Some(words.join(" ")),
+                ));
+            }
+            kind @ (EventKind::Symb
+            | EventKind::FootnoteReference
+            | EventKind::CodeLanguage
+            | EventKind::RawFormat
+            | EventKind::NoteLabel
+            | EventKind::ReferenceKey
+            | EventKind::Class
+            | EventKind::Id
+            | EventKind::Key
+            | EventKind::Value) => {
+                out.push(SemanticEvent::Leaf(kind.clone(), Some(src(event))));
+            }
+            kind @ (EventKind::HardBreak
+            | EventKind::NonBreakingSpace
+            | EventKind::ThematicBreak
+            | EventKind::ImageMarker
+            | EventKind::CheckboxChecked
+            | EventKind::CheckboxUnchecked
+            | EventKind::LeftSingleQuote
+            | EventKind::RightSingleQuote
+            | EventKind::LeftDoubleQuote
+            | EventKind::RightDoubleQuote
+            | EventKind::EnDash
+            | EventKind::EmDash
+            | EventKind::Ellipses
+            | EventKind::SeparatorDefault
+            | EventKind::SeparatorLeft
+            | EventKind::SeparatorRight
+            | EventKind::SeparatorCenter
+            | EventKind::AttrClassMarker
+            | EventKind::AttrIdMarker
+            | EventKind::AttrEqualMarker) => out.push(SemanticEvent::Leaf(kind.clone(), None)),
+        }
+    }
+
+    out
+}
+
+/// Whether the text of `container` is kept as it is.
+fn is_verbatim(container: &Container) -> bool {
+    matches!(
+        container,
+        Container::CodeBlock | Container::Verbatim | Container::InlineMath | Container::DisplayMath
+    )
+}
+
+/// Collapse whitespace in text outside code blocks, verbatim and math, and
+/// drop text that only consists of whitespace at the edges of containers.
+fn normalize_text(events: &mut Vec<SemanticEvent>) {
+    let mut in_verbatim = false;
+    for event in events.iter_mut() {
+        match event {
+            SemanticEvent::Open(container, _) if is_verbatim(container) => in_verbatim = true,
+            SemanticEvent::Close(container) if is_verbatim(container) => in_verbatim = false,
+            SemanticEvent::Text(text) if !in_verbatim => {
+                let mut collapsed = String::with_capacity(text.len());
+                let mut space = false;
+                for c in text.chars() {
+                    // Non-breaking spaces are written as `\ ` and are not
+                    // whitespace for Djot.
+                    if c.is_whitespace() && c != '\u{a0}' {
+                        space = true;
+                        continue;
+                    }
+                    if space && !collapsed.is_empty() {
+                        collapsed.push(' ');
+                    }
+                    space = false;
+                    collapsed.push(c);
+                }
+                if space {
+                    collapsed.push(' ');
+                }
+                *text = collapsed;
+            }
+            _ => {}
+        }
+    }
+
+    // Whitespace next to block boundaries is not content.
+    let len = events.len();
+    for i in 0..len {
+        let at_block_edge = |j: Option<usize>| match j.and_then(|j| events.get(j)) {
+            None => true,
+            Some(SemanticEvent::Open(container, _)) | Some(SemanticEvent::Close(container)) => {
+                container.is_block() || matches!(container, Container::Row | Container::Cell)
+            }
+            Some(SemanticEvent::Leaf(kind, _)) => matches!(
+                kind,
+                EventKind::HardBreak | EventKind::CheckboxChecked | EventKind::CheckboxUnchecked
+            ),
+            Some(SemanticEvent::Text(_)) => false,
+        };
+        let before = at_block_edge(i.checked_sub(1));
+        let after = at_block_edge(Some(i + 1));
+        if let SemanticEvent::Text(text) = &mut events[i] {
+            if before && text.starts_with(' ') {
+                text.remove(0);
+            }
+            if after && text.ends_with(' ') {
+                text.pop();
+            }
+        }
+    }
+    events.retain(|event| !matches!(event, SemanticEvent::Text(text) if text.is_empty()));
+}
+
+/// Normalize the details of a list with `styles` that formatting with
+/// `config` may change: with a `.` delimiter, a `-` bullet and no spacing,
+/// e.g. `list|(a)|start=1|tight` as `list|a.|start=1` and
+/// `list|+X|start=1|tight` as `list|-X|start=1`.
+fn ignore_list_changes(styles: &mut [ListStyle], details: &mut Details, config: &FmtConfig) {
+    for style in styles.iter_mut() {
+        *style = match *style {
+            ListStyle::Bullet(_) if config.bullet != Bullet::Preserve => ListStyle::Bullet('-'),
+            ListStyle::Task(_) if config.bullet != Bullet::Preserve => ListStyle::Task('-'),
+            ListStyle::Ordered(enumerator, _)
+                if config.list_delimiter != ListDelimiter::Preserve =>
+            {
+                ListStyle::Ordered(enumerator, Delimiter::Period)
+            }
+            style => style,
+        };
+    }
+    if let Details::List { loose, .. } = details {
+        if config.list_spacing != ListSpacing::Preserve {
+            *loose = None;
+        }
+    }
+}
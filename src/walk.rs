@@ -0,0 +1,86 @@
+// SPDX-FileCopyrightText: 2026 Chen Linxuan <me@black-desk.cn>
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Expansion of directory inputs into the Djot files they contain.
+
+/// Globs selecting the files formatted inside a directory, unless overridden
+/// with `--include`.
+const DEFAULT_INCLUDE: [&str; 2] = ["*.dj", "*.djot"];
+
+/// Name of the djotfmt specific ignore file, using `.gitignore` syntax.
+const IGNORE_FILE_NAME: &str = ".djotfmtignore";
+
+fn to_io_error(err: ignore::Error) -> std::io::Error {
+    std::io::Error::other(err)
+}
+
+fn is_hidden(name: &std::ffi::OsStr) -> bool {
+    name.as_encoded_bytes().starts_with(b".")
+}
+
+/// Expand every directory in `inputs` into the Djot files below it.
+///
+/// Files given explicitly are kept as they are. Inside directories, files
+/// whose name matches `include` (or `*.dj` and `*.djot` if empty) are
+/// collected, skipping hidden files, files ignored by `.gitignore`, `.ignore`
+/// or `.djotfmtignore` files, and files matching any of the `exclude` globs.
+/// The files of each directory are sorted by path so the output is
+/// deterministic.
+pub fn expand_inputs(
+    inputs: &[std::path::PathBuf],
+    include: &[String],
+    exclude: &[String],
+) -> std::io::Result<Vec<std::path::PathBuf>> {
+    let mut files = Vec::new();
+
+    for input in inputs {
+        if !input.is_dir() {
+            files.push(input.clone());
+            continue;
+        }
+
+        log::trace!("Walking directory {}", input.display());
+
+        // File types have a lower precedence than ignore files, so included
+        // files can still be ignored. Overrides have the highest one.
+        let mut types = ignore::types::TypesBuilder::new();
+        if include.is_empty() {
+            for glob in DEFAULT_INCLUDE {
+                types.add("djot", glob).map_err(to_io_error)?;
+            }
+        }
+        for glob in include {
+            types.add("djot", glob).map_err(to_io_error)?;
+        }
+        let types = types.select("djot").build().map_err(to_io_error)?;
+
+        let mut overrides = ignore::overrides::OverrideBuilder::new(input);
+        for glob in exclude {
+            overrides.add(&format!("!{}", glob)).map_err(to_io_error)?;
+        }
+        let overrides = overrides.build().map_err(to_io_error)?;
+
+        let walker = ignore::WalkBuilder::new(input)
+            .types(types)
+            .overrides(overrides)
+            .add_custom_ignore_filename(IGNORE_FILE_NAME)
+            .require_git(false)
+            // Files matching the file types are whitelisted, which takes
+            // precedence over skipping hidden files.
+            .filter_entry(|entry| entry.depth() == 0 || !is_hidden(entry.file_name()))
+            .sort_by_file_path(|a, b| a.cmp(b))
+            .build();
+
+        for entry in walker {
+            let entry = entry.map_err(to_io_error)?;
+            if !entry.file_type().is_some_and(|t| t.is_file()) {
+                continue;
+            }
+            log::trace!("Found file {}", entry.path().display());
+            files.push(entry.into_path());
+        }
+    }
+
+    Ok(files)
+}
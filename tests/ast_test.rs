@@ -0,0 +1,58 @@
+// SPDX-FileCopyrightText: 2026 Chen Linxuan <me@black-desk.cn>
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use libtest_mimic::{Arguments, Failed, Trial};
+use pretty_assertions::assert_eq;
+
+fn run_ast_test(input_path: std::path::PathBuf) -> Result<(), Failed> {
+    let input = std::fs::read_to_string(&input_path).map_err(|e| e.to_string())?;
+    let expected =
+        std::fs::read_to_string(input_path.with_extension("ast")).map_err(|e| e.to_string())?;
+
+    let doc = djotfmt::ast::parse(&input);
+
+    assert_eq!(
+        doc.to_string(),
+        expected,
+        "test case {:?}",
+        input_path.file_stem().unwrap()
+    );
+    Ok(())
+}
+
+/// Every event the parser produces formats to an annotation which parses
+/// back to it.
+fn run_event_kind_test(input_path: std::path::PathBuf) -> Result<(), Failed> {
+    let input = std::fs::read_to_string(&input_path).map_err(|e| e.to_string())?;
+
+    for event in djotfmt::parser::parse_events(&input) {
+        let kind: djotfmt::parser::EventKind = event.annot().parse()?;
+        assert_eq!(kind, event.kind);
+    }
+    Ok(())
+}
+
+fn main() {
+    let args = Arguments::from_args();
+
+    let mut trials: Vec<_> = glob::glob("./tests/ast/*.dj")
+        .unwrap()
+        .map(|entry| {
+            let path = entry.unwrap();
+            let name = path.file_stem().unwrap().to_str().unwrap().to_string();
+            Trial::test(format!("ast::{}", name), move || run_ast_test(path))
+        })
+        .collect();
+    assert!(!trials.is_empty(), "no test cases found");
+
+    for entry in glob::glob("./tests/*.in").unwrap() {
+        let path = entry.unwrap();
+        let name = path.file_stem().unwrap().to_str().unwrap().to_string();
+        trials.push(Trial::test(format!("event_kind::{}", name), move || {
+            run_event_kind_test(path)
+        }));
+    }
+
+    libtest_mimic::run(&args, trials).exit();
+}
@@ -0,0 +1,219 @@
+// SPDX-FileCopyrightText: 2026 Chen Linxuan <me@black-desk.cn>
+//
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use pretty_assertions::assert_eq;
+use std::path::Path;
+use std::process::Output;
+
+const UNFORMATTED: &str = "#  Title\n\nSome   text.\n";
+const FORMATTED: &str = "# Title\n\nSome text.\n";
+
+/// Run djotfmt in `dir` with `args`.
+fn djotfmt(dir: &Path, args: &[&str]) -> Output {
+    std::process::Command::new(env!("CARGO_BIN_EXE_djotfmt"))
+        .current_dir(dir)
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+/// Write `content` to `path` below `dir`, creating missing directories.
+fn write(dir: &Path, path: &str, content: &str) {
+    let path = dir.join(path);
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(path, content).unwrap();
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+#[test]
+fn check_exit_code() {
+    let dir = tempfile::tempdir().unwrap();
+    write(dir.path(), "formatted.dj", FORMATTED);
+    write(dir.path(), "unformatted.dj", UNFORMATTED);
+
+    let output = djotfmt(dir.path(), &["--check", "formatted.dj"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "");
+
+    let output = djotfmt(dir.path(), &["--check", "formatted.dj", "unformatted.dj"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stdout(&output), "unformatted.dj\n");
+
+    // Nothing is written.
+    let content = std::fs::read_to_string(dir.path().join("unformatted.dj")).unwrap();
+    assert_eq!(content, UNFORMATTED);
+}
+
+#[test]
+fn diff_output() {
+    let dir = tempfile::tempdir().unwrap();
+    write(dir.path(), "formatted.dj", FORMATTED);
+    write(dir.path(), "unformatted.dj", UNFORMATTED);
+
+    let output = djotfmt(dir.path(), &["--diff", "formatted.dj", "unformatted.dj"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        stdout(&output),
+        "--- unformatted.dj\n\
+         +++ unformatted.dj\n\
+         @@ -1,3 +1,3 @@\n\
+         -#  Title\n\
+         +# Title\n \n\
+         -Some   text.\n\
+         +Some text.\n"
+    );
+
+    let output = djotfmt(dir.path(), &["--diff", "--check", "unformatted.dj"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stdout(&output).starts_with("--- unformatted.dj\n"));
+}
+
+#[test]
+fn config_discovery() {
+    let dir = tempfile::tempdir().unwrap();
+    write(
+        dir.path(),
+        "djotfmt.toml",
+        "thematic_break = \"---\"\nthematic_break_fill = false\n",
+    );
+    write(dir.path(), "top.dj", "***\n");
+    // Closer files override the options they set and inherit the others.
+    write(
+        dir.path(),
+        "sub/.djotfmt.toml",
+        "thematic_break = \"***\"\n",
+    );
+    write(dir.path(), "sub/nested.dj", "---\n");
+    // `root = true` stops the search.
+    write(dir.path(), "sub/root/djotfmt.toml", "root = true\n");
+    write(dir.path(), "sub/root/rooted.dj", "---\n");
+
+    let output = djotfmt(
+        dir.path(),
+        &["top.dj", "sub/nested.dj", "sub/root/rooted.dj"],
+    );
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        stdout(&output),
+        format!("---\n***\n{}\n", ["*"; 36].join(" "))
+    );
+
+    // Only the directories a file is in are searched, not the ones a path
+    // to it goes through.
+    write(dir.path(), "proj/djotfmt.toml", "bullet = \"*\"\n");
+    write(dir.path(), "docs/list.dj", "- a\n");
+    let output = djotfmt(&dir.path().join("proj"), &["../docs/list.dj"]);
+    assert_eq!(stdout(&output), "- a\n");
+
+    // Options given on the command line override configuration files.
+    let output = djotfmt(dir.path(), &["-c", "10", "sub/root/rooted.dj"]);
+    assert_eq!(stdout(&output), "* * * * *\n");
+}
+
+#[test]
+fn invalid_config() {
+    let dir = tempfile::tempdir().unwrap();
+    write(dir.path(), "djotfmt.toml", "unknown_option = 1\n");
+    write(dir.path(), "a.dj", FORMATTED);
+
+    let output = djotfmt(dir.path(), &["a.dj"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stdout(&output), "");
+}
+
+#[test]
+fn walk_directories() {
+    let dir = tempfile::tempdir().unwrap();
+    for path in [
+        "docs/a.dj",
+        "docs/b.djot",
+        "docs/c.md",
+        "docs/.hidden.dj",
+        "docs/sub/d.dj",
+        "docs/ignored/e.dj",
+        "docs/excluded.dj",
+    ] {
+        write(dir.path(), path, UNFORMATTED);
+    }
+    write(dir.path(), "docs/.djotfmtignore", "ignored/\n");
+
+    let output = djotfmt(dir.path(), &["--check", "--exclude", "excluded.dj", "docs"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stdout(&output), "docs/a.dj\ndocs/b.djot\ndocs/sub/d.dj\n");
+
+    let output = djotfmt(dir.path(), &["--check", "--include", "*.md", "docs"]);
+    assert_eq!(stdout(&output), "docs/c.md\n");
+}
+
+#[test]
+fn jobs_keep_order() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut expected = String::new();
+    for i in 0..32 {
+        write(
+            dir.path(),
+            &format!("docs/{:02}.dj", i),
+            &format!("#  File\n\nNumber   {}.\n", i),
+        );
+        expected.push_str(&format!("# File\n\nNumber {}.\n", i));
+    }
+
+    let output = djotfmt(dir.path(), &["--jobs", "4", "docs"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), expected);
+}
+
+#[test]
+fn inplace() {
+    let dir = tempfile::tempdir().unwrap();
+    write(dir.path(), "a.dj", UNFORMATTED);
+
+    let output = djotfmt(dir.path(), &["-i", "a.dj"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "");
+    let content = std::fs::read_to_string(dir.path().join("a.dj")).unwrap();
+    assert_eq!(content, FORMATTED);
+
+    // No temporary file is left behind.
+    let names: Vec<_> = std::fs::read_dir(dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(names, ["a.dj"]);
+}
+
+#[cfg(unix)]
+#[test]
+fn inplace_keeps_permissions() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    write(dir.path(), "a.dj", UNFORMATTED);
+    let path = dir.path().join("a.dj");
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o640)).unwrap();
+
+    let output = djotfmt(dir.path(), &["-i", "a.dj"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), FORMATTED);
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o640);
+}
+
+#[cfg(unix)]
+#[test]
+fn inplace_follows_symlinks() {
+    let dir = tempfile::tempdir().unwrap();
+    write(dir.path(), "a.dj", UNFORMATTED);
+    std::os::unix::fs::symlink("a.dj", dir.path().join("link.dj")).unwrap();
+
+    let output = djotfmt(dir.path(), &["-i", "link.dj"]);
+    assert_eq!(output.status.code(), Some(0));
+    let link = std::fs::symlink_metadata(dir.path().join("link.dj")).unwrap();
+    assert!(link.file_type().is_symlink());
+    let content = std::fs::read_to_string(dir.path().join("a.dj")).unwrap();
+    assert_eq!(content, FORMATTED);
+}
@@ -25,22 +25,28 @@ fn discover_tests(base: &str, extensions: &[&str]) -> Vec<Vec<std::path::PathBuf
         .collect()
 }
 
-fn parse_max_cols(content: &str) -> usize {
-    let mut max_cols = 72;
-    for line in content.lines() {
-        if let Some(idx) = line.find("@columns:") {
-            let rest = &line[idx + "@columns:".len()..];
-            let num = rest
-                .trim_start()
-                .split(|c: char| !c.is_ascii_digit())
-                .next()
-                .unwrap_or("");
-            if let Ok(cols) = num.parse::<usize>() {
-                max_cols = cols;
-            }
+/// Read the formatter options of a test case from `@key: value` directives,
+/// which use the keys of configuration files. `@columns` is `max_cols`.
+/// Values may have single spaces, like `@thematic_break: - - -`.
+fn parse_config(content: &str) -> djotfmt::fmt::FmtConfig {
+    let directive = regex::Regex::new(r"@(\w+):\s*([\w.*-]+(?: [\w.*-]+)*)").unwrap();
+    let mut toml = String::new();
+    for caps in directive.captures_iter(content) {
+        let key = match &caps[1] {
+            "columns" => "max_cols",
+            key => key,
+        };
+        let value = &caps[2];
+        if value.parse::<i64>().is_ok() || value == "true" || value == "false" {
+            toml.push_str(&format!("{} = {}\n", key, value));
+        } else {
+            toml.push_str(&format!("{} = {:?}\n", key, value));
         }
     }
-    max_cols
+    let file_config: djotfmt::config::Config = toml.parse().unwrap();
+    let mut config = djotfmt::fmt::FmtConfig::default();
+    file_config.apply(&mut config);
+    config
 }
 
 fn run_format_test(
@@ -50,8 +56,7 @@ fn run_format_test(
     let input = std::fs::read_to_string(&input_path).map_err(|e| e.to_string())?;
     let expected = std::fs::read_to_string(&expected_path).map_err(|e| e.to_string())?;
 
-    let max_cols = parse_max_cols(&input);
-    let config = djotfmt::fmt::FmtConfig { max_cols };
+    let config = parse_config(&input);
 
     let output = djotfmt::fmt::format(&input, &config);
 
@@ -67,8 +72,7 @@ fn run_format_test(
 fn run_idempotent_test(path: std::path::PathBuf) -> Result<(), Failed> {
     let input = std::fs::read_to_string(&path).map_err(|e| e.to_string())?;
 
-    let max_cols = parse_max_cols(&input);
-    let config = djotfmt::fmt::FmtConfig { max_cols };
+    let config = parse_config(&input);
 
     let output = djotfmt::fmt::format(&input, &config);
 
@@ -81,6 +85,31 @@ fn run_idempotent_test(path: std::path::PathBuf) -> Result<(), Failed> {
     Ok(())
 }
 
+fn run_verify_test(path: std::path::PathBuf) -> Result<(), Failed> {
+    let input = std::fs::read_to_string(&path).map_err(|e| e.to_string())?;
+
+    let config = parse_config(&input);
+
+    let output = djotfmt::fmt::format(&input, &config);
+
+    djotfmt::verify::verify(&input, &output, &config).map_err(|e| {
+        format!(
+            "verify test failed for {:?}: {}",
+            path.file_stem().unwrap(),
+            e
+        )
+    })?;
+    Ok(())
+}
+
+/// Test cases the formatter is known to change the meaning of.
+const VERIFY_KNOWN_FAILURES: &[&str] = &[
+    // The closing brace of a multi-line block attribute loses its indentation.
+    "handle-nest-attrs",
+    // Short table rows are padded with empty cells.
+    "table",
+];
+
 fn main() {
     let args = Arguments::from_args();
     let mut trials = Vec::new();
@@ -106,11 +135,18 @@ fn main() {
             .unwrap()
             .to_string();
 
-        // All tests pass — no skips needed
-
+        let verify_path = input_path.clone();
         trials.push(Trial::test(format!("fmt::{}", name), move || {
             run_format_test(input_path, expected_path)
         }));
+
+        let known_failure = VERIFY_KNOWN_FAILURES.contains(&name.as_str());
+        trials.push(
+            Trial::test(format!("fmt::verify::{}", name), move || {
+                run_verify_test(verify_path)
+            })
+            .with_ignored_flag(known_failure),
+        );
     }
 
     let idem_tests = discover_tests("./tests/", &["out"]);
@@ -17,12 +17,18 @@ fn format_events(events: &[djotfmt::parser::Event]) -> String {
         if i == 0 {
             out.push_str(&format!(
                 "[{{ startpos: {}, endpos: {}, annot: {:?} }}{}",
-                ev.startpos, ev.endpos, ev.annot, comma
+                ev.startpos,
+                ev.endpos,
+                ev.annot(),
+                comma
             ));
         } else {
             out.push_str(&format!(
                 "\n {{ startpos: {}, endpos: {}, annot: {:?} }}{}",
-                ev.startpos, ev.endpos, ev.annot, comma
+                ev.startpos,
+                ev.endpos,
+                ev.annot(),
+                comma
             ));
         }
     }
@@ -327,7 +333,7 @@ fn run_parser_event_test(test_file: &str, case_index: usize, input: &str) -> Res
         .map(|ev| djotfmt::parser::Event {
             startpos: map.get(ev.startpos).copied().unwrap_or(0),
             endpos: map.get(ev.endpos).copied().unwrap_or(0),
-            annot: ev.annot,
+            kind: ev.kind,
         })
         .collect();
     let actual = format_events(&utf16_events);